@@ -0,0 +1,316 @@
+//! A content-addressed cache of synthesized audio clips.
+//!
+//! Every clip is stored as `<key>.<extension>` next to a `<key>.toml` file
+//! holding its metadata.  The key is a SHA-256 over everything which
+//! influences the resulting audio.
+
+use crate::encoder::{Clip, Encoder};
+use crate::{Result, ResultExt};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::{self, read_to_string, write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use url::Url;
+use xdg::BaseDirectories;
+
+/// How long an uploaded clip is assumed to stay available.
+pub const URL_LIFETIME: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// Everything that determines how a clip sounds.
+pub struct Key<'a> {
+  pub engine: &'a str,
+  pub language: Option<&'a str>,
+  pub markup: &'a str,
+  pub words_per_minute: &'a str,
+  pub amplitude: u8,
+  pub encoder: Encoder
+}
+
+impl Key<'_> {
+  fn digest(&self) -> String {
+    let mut hasher = Sha256::new();
+    for field in [
+      self.engine,
+      self.language.unwrap_or_default(),
+      self.markup,
+      self.words_per_minute,
+      &self.amplitude.to_string(),
+      &format!("{:?}", self.encoder)
+    ]
+    .iter()
+    {
+      hasher.update(field.as_bytes());
+      hasher.update([0]);
+    }
+    hasher
+      .finalize()
+      .iter()
+      .map(|byte| format!("{:02x}", byte))
+      .collect()
+  }
+}
+
+#[derive(Deserialize, Serialize)]
+struct Metadata {
+  extension: String,
+  duration_millis: u64,
+  size: u64,
+  used: u64,
+  url: Option<String>,
+  uploaded: Option<u64>
+}
+
+/// A clip stored in the cache.
+pub struct Entry {
+  key: String,
+  metadata: Metadata
+}
+
+impl Entry {
+  /// The file name of the audio data, relative to the cache directory.
+  pub fn file_name(&self) -> String {
+    format!("{}.{}", self.key, self.metadata.extension)
+  }
+
+  pub fn extension(&self) -> &str {
+    &self.metadata.extension
+  }
+
+  pub fn duration(&self) -> Duration {
+    Duration::from_millis(self.metadata.duration_millis)
+  }
+
+  /// A previously uploaded URL which is likely still valid.
+  pub fn url(&self) -> Option<Url> {
+    let uploaded = self.metadata.uploaded?;
+    if now().saturating_sub(uploaded) > URL_LIFETIME.as_secs() {
+      return None;
+    }
+    self.metadata.url.as_deref().and_then(|url| Url::parse(url).ok())
+  }
+}
+
+pub struct ClipCache {
+  directory: PathBuf,
+  max_size: u64
+}
+
+impl ClipCache {
+  /// Open the cache in `$XDG_CACHE_HOME/ronor/clips`.
+  pub fn open(max_size: u64) -> Result<Self> {
+    let directory =
+      BaseDirectories::with_prefix("ronor")?.create_cache_directory("clips")?;
+    Ok(ClipCache {
+      directory,
+      max_size
+    })
+  }
+
+  pub fn directory(&self) -> &Path {
+    &self.directory
+  }
+
+  pub fn get(&self, key: &Key) -> Result<Option<Entry>> {
+    let key = key.digest();
+    let metadata_path = self.metadata_path(&key);
+    if !metadata_path.exists() {
+      return Ok(None);
+    }
+    let mut entry = Entry {
+      metadata: toml::from_str(&read_to_string(&metadata_path)?)
+        .chain_err(|| "Failed to read clip cache metadata")?,
+      key
+    };
+    if !self.directory.join(entry.file_name()).exists() {
+      return Ok(None);
+    }
+    entry.metadata.used = now();
+    self.write_metadata(&entry)?;
+    Ok(Some(entry))
+  }
+
+  pub fn insert(&self, key: &Key, clip: Clip) -> Result<Entry> {
+    let entry = Entry {
+      key: key.digest(),
+      metadata: Metadata {
+        extension: clip.extension.to_string(),
+        duration_millis: clip.duration.as_millis() as u64,
+        size: clip.data.len() as u64,
+        used: now(),
+        url: None,
+        uploaded: None
+      }
+    };
+    self.prune(self.max_size.saturating_sub(entry.metadata.size), None)?;
+    write(self.directory.join(entry.file_name()), &clip.data)?;
+    self.write_metadata(&entry)?;
+    Ok(entry)
+  }
+
+  pub fn read(&self, entry: &Entry) -> Result<Vec<u8>> {
+    Ok(fs::read(self.directory.join(entry.file_name()))?)
+  }
+
+  /// Remember where a clip has been uploaded to.
+  pub fn set_url(&self, entry: &mut Entry, url: &Url) -> Result<()> {
+    entry.metadata.url = Some(url.to_string());
+    entry.metadata.uploaded = Some(now());
+    self.write_metadata(entry)
+  }
+
+  /// Remove clips not used for longer than `max_age`, then remove the least
+  /// recently used clips until the cache fits into `max_size` bytes.
+  ///
+  /// Returns the number of removed clips and bytes.
+  pub fn prune(&self, max_size: u64, max_age: Option<Duration>) -> Result<(usize, u64)> {
+    let mut entries = Vec::new();
+    for dir_entry in fs::read_dir(&self.directory)? {
+      let path = dir_entry?.path();
+      if path.extension().is_some_and(|extension| extension == "toml") {
+        if let Some(key) = path.file_stem().and_then(|stem| stem.to_str()) {
+          match read_to_string(&path).map(|s| toml::from_str::<Metadata>(&s)) {
+            Ok(Ok(metadata)) => entries.push(Entry {
+              key: key.to_string(),
+              metadata
+            }),
+            _ => fs::remove_file(&path)?
+          }
+        }
+      }
+    }
+    entries.sort_by_key(|entry| std::cmp::Reverse(entry.metadata.used));
+    let oldest = max_age.map(|max_age| now().saturating_sub(max_age.as_secs()));
+    let mut size = 0;
+    let mut removed = (0, 0);
+    for entry in entries.iter() {
+      size += entry.metadata.size;
+      if size > max_size || oldest.is_some_and(|oldest| entry.metadata.used < oldest) {
+        self.remove(entry)?;
+        removed.0 += 1;
+        removed.1 += entry.metadata.size;
+      }
+    }
+    Ok(removed)
+  }
+
+  fn remove(&self, entry: &Entry) -> Result<()> {
+    let audio = self.directory.join(entry.file_name());
+    if audio.exists() {
+      fs::remove_file(audio)?;
+    }
+    fs::remove_file(self.metadata_path(&entry.key))?;
+    Ok(())
+  }
+
+  fn metadata_path(&self, key: &str) -> PathBuf {
+    self.directory.join(format!("{}.toml", key))
+  }
+
+  fn write_metadata(&self, entry: &Entry) -> Result<()> {
+    write(
+      self.metadata_path(&entry.key),
+      toml::to_string(&entry.metadata).chain_err(|| "Failed to write clip cache metadata")?
+    )?;
+    Ok(())
+  }
+}
+
+fn now() -> u64 {
+  SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map_or(0, |duration| duration.as_secs())
+}
+
+/// Parse a size like `4096`, `512K`, `100M` or `1G`.
+pub fn parse_size(s: &str) -> Result<u64> {
+  let s = s.trim();
+  let (number, factor) = match s.char_indices().last() {
+    Some((index, 'K')) | Some((index, 'k')) => (&s[..index], 1024),
+    Some((index, 'M')) | Some((index, 'm')) => (&s[..index], 1024 * 1024),
+    Some((index, 'G')) | Some((index, 'g')) => (&s[..index], 1024 * 1024 * 1024),
+    _ => (s, 1)
+  };
+  number
+    .trim()
+    .parse::<u64>()?
+    .checked_mul(factor)
+    .ok_or_else(|| format!("Size '{}' is too large", s).into())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn parses_sizes() {
+    assert_eq!(parse_size("4096").unwrap(), 4096);
+    assert_eq!(parse_size(" 512K ").unwrap(), 512 * 1024);
+    assert_eq!(parse_size("100m").unwrap(), 100 * 1024 * 1024);
+    assert_eq!(parse_size("1 G").unwrap(), 1024 * 1024 * 1024);
+  }
+
+  #[test]
+  fn rejects_invalid_sizes() {
+    assert!(parse_size("").is_err());
+    assert!(parse_size("G").is_err());
+    assert!(parse_size("-1K").is_err());
+    assert!(parse_size("1T").is_err());
+    assert!(parse_size("99999999999999G").is_err());
+    assert!(parse_size("18446744073709551615").is_ok());
+  }
+
+  /// A cache in a fresh temporary directory, holding a clip of `size`
+  /// bytes last used at each of `used`.
+  fn cache(name: &str, size: u64, used: &[u64]) -> ClipCache {
+    let directory = std::env::temp_dir().join(format!("ronor-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&directory);
+    fs::create_dir_all(&directory).unwrap();
+    let cache = ClipCache {
+      directory,
+      max_size: u64::MAX
+    };
+    for (index, used) in used.iter().enumerate() {
+      let entry = Entry {
+        key: format!("clip{}", index),
+        metadata: Metadata {
+          extension: String::from("mp3"),
+          duration_millis: 1000,
+          size,
+          used: *used,
+          url: None,
+          uploaded: None
+        }
+      };
+      write(cache.directory.join(entry.file_name()), vec![0; size as usize]).unwrap();
+      cache.write_metadata(&entry).unwrap();
+    }
+    cache
+  }
+
+  fn remaining(cache: &ClipCache) -> Vec<String> {
+    let mut names: Vec<String> = fs::read_dir(&cache.directory)
+      .unwrap()
+      .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+      .filter(|name| name.ends_with(".mp3"))
+      .collect();
+    names.sort();
+    fs::remove_dir_all(&cache.directory).unwrap();
+    names
+  }
+
+  #[test]
+  fn prunes_least_recently_used_first() {
+    let cache = cache("lru", 10, &[200, 300, 100, 400]);
+    assert_eq!(cache.prune(25, None).unwrap(), (2, 20));
+    assert_eq!(remaining(&cache), vec!["clip1.mp3", "clip3.mp3"]);
+  }
+
+  #[test]
+  fn prunes_by_age() {
+    let now = now();
+    let cache = cache("age", 10, &[now - 10, now - 7200, now]);
+    assert_eq!(cache.prune(u64::MAX, Some(Duration::from_secs(3600))).unwrap(), (1, 10));
+    assert_eq!(remaining(&cache), vec!["clip0.mp3", "clip2.mp3"]);
+  }
+}
@@ -0,0 +1,189 @@
+//! A minimal cloud queue, serving the tracks of a local directory.
+//!
+//! Players ask a cloud queue for windows of upcoming items (`itemWindow`),
+//! its `version` and `context`, and report what they played (`timePlayed`),
+//! all relative to the queue base URL `/queue`.  The media files themselves
+//! are served from `/media/<item id>`.
+
+use crate::{Result, ResultExt};
+use serde_json::{json, Value};
+use std::fs::{read_dir, File};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tiny_http::{Header, Method, Request, Response, Server};
+use url::Url;
+
+/// Never hand out more than this many items at once.
+const MAX_WINDOW: usize = 50;
+
+struct Track {
+  path: PathBuf,
+  name: String,
+  content_type: &'static str
+}
+
+pub struct CloudQueue {
+  name: String,
+  tracks: Vec<Track>,
+  version: String
+}
+
+impl CloudQueue {
+  /// All playable files in `directory`, sorted by file name.
+  pub fn from_directory(directory: &Path) -> Result<Self> {
+    let mut tracks = Vec::new();
+    for entry in read_dir(directory)
+      .chain_err(|| format!("Failed to read directory '{}'", directory.display()))?
+    {
+      let path = entry?.path();
+      if let Some(content_type) = content_type(&path) {
+        let name = path
+          .file_stem()
+          .map(|stem| stem.to_string_lossy().to_string())
+          .unwrap_or_default();
+        tracks.push(Track {
+          path,
+          name,
+          content_type
+        });
+      }
+    }
+    if tracks.is_empty() {
+      return Err(format!("No playable files in '{}'", directory.display()).into());
+    }
+    tracks.sort_by(|a, b| a.path.cmp(&b.path));
+    let name = directory
+      .canonicalize()?
+      .file_name()
+      .map(|name| name.to_string_lossy().to_string())
+      .unwrap_or_default();
+    let version = SystemTime::now()
+      .duration_since(UNIX_EPOCH)
+      .map(|duration| duration.as_secs().to_string())
+      .unwrap_or_default();
+    Ok(CloudQueue {
+      name,
+      tracks,
+      version
+    })
+  }
+
+  pub fn len(&self) -> usize {
+    self.tracks.len()
+  }
+
+  pub fn version(&self) -> &str {
+    &self.version
+  }
+
+  /// Serve the queue on `address` (`HOST:PORT`) from a background thread
+  /// and return the base URL to pass to `loadCloudQueue`.
+  ///
+  /// `HOST` has to be an address the players can reach.
+  pub fn start(self, address: &str) -> Result<Url> {
+    let base = Url::parse(&format!("http://{}/", address))
+      .chain_err(|| format!("Invalid address to serve on: '{}'", address))?;
+    let server = Server::http(address)
+      .map_err(|e| format!("Failed to listen on {}: {}", address, e))?;
+    let media = base.join("media/")?;
+    thread::spawn(move || {
+      for request in server.incoming_requests() {
+        let _ = self.respond(request, &media);
+      }
+    });
+    Ok(base.join("queue")?)
+  }
+
+  fn respond(&self, request: Request, media: &Url) -> std::io::Result<()> {
+    let url = match media.join(request.url()) {
+      Ok(url) => url,
+      Err(_) => return request.respond(Response::empty(400))
+    };
+    let query = |key: &str| {
+      url
+        .query_pairs()
+        .find(|(name, _)| name == key)
+        .map(|(_, value)| value.to_string())
+    };
+    match (request.method(), url.path()) {
+      (Method::Get, "/queue/version") => request.respond(json_response(&json!({
+        "contextVersion": self.version,
+        "queueVersion": self.version
+      }))),
+      (Method::Get, "/queue/context") => request.respond(json_response(&json!({
+        "contextVersion": self.version,
+        "queueVersion": self.version,
+        "container": { "type": "playlist", "name": self.name }
+      }))),
+      (Method::Get, "/queue/itemWindow") => {
+        let index = query("itemId")
+          .and_then(|id| id.parse::<usize>().ok())
+          .filter(|index| *index < self.tracks.len())
+          .unwrap_or(0);
+        let window = |key: &str| {
+          query(key)
+            .and_then(|size| size.parse::<usize>().ok())
+            .unwrap_or(10)
+            .min(MAX_WINDOW)
+        };
+        let start = index.saturating_sub(window("previousWindowSize"));
+        let end = (index + 1 + window("upcomingWindowSize")).min(self.tracks.len());
+        let items: Vec<Value> = (start..end).map(|id| self.item(id, media)).collect();
+        request.respond(json_response(&json!({
+          "includesBeginningOfQueue": start == 0,
+          "includesEndOfQueue": end == self.tracks.len(),
+          "contextVersion": self.version,
+          "queueVersion": self.version,
+          "items": items
+        })))
+      }
+      (Method::Post, "/queue/timePlayed") => request.respond(Response::empty(200)),
+      (Method::Get, path) => {
+        let track = path
+          .strip_prefix("/media/")
+          .and_then(|id| id.parse::<usize>().ok())
+          .and_then(|id| self.tracks.get(id));
+        match track.map(|track| (File::open(&track.path), track.content_type)) {
+          Some((Ok(file), content_type)) => {
+            request.respond(Response::from_file(file).with_header(header(content_type)))
+          }
+          _ => request.respond(Response::empty(404))
+        }
+      }
+      _ => request.respond(Response::empty(404))
+    }
+  }
+
+  fn item(&self, id: usize, media: &Url) -> Value {
+    let track = &self.tracks[id];
+    json!({
+      "id": id.to_string(),
+      "track": {
+        "name": track.name,
+        "mediaUrl": media.join(&id.to_string()).map(String::from).unwrap_or_default(),
+        "contentType": track.content_type
+      }
+    })
+  }
+}
+
+fn content_type(path: &Path) -> Option<&'static str> {
+  match path.extension()?.to_str()?.to_lowercase().as_str() {
+    "mp3" => Some("audio/mpeg"),
+    "flac" => Some("audio/flac"),
+    "m4a" | "mp4" => Some("audio/mp4"),
+    "aac" => Some("audio/aac"),
+    "ogg" => Some("audio/ogg"),
+    "wav" => Some("audio/wav"),
+    _ => None
+  }
+}
+
+fn header(content_type: &str) -> Header {
+  Header::from_bytes(&b"Content-Type"[..], content_type.as_bytes()).unwrap()
+}
+
+fn json_response(value: &Value) -> Response<std::io::Cursor<Vec<u8>>> {
+  Response::from_string(value.to_string()).with_header(header("application/json"))
+}
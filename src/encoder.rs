@@ -0,0 +1,309 @@
+//! Turning WAV data from a TTS engine into audio clips Sonos can play.
+
+use crate::{Result, ResultExt};
+use mp3lame_encoder::{Bitrate, Builder, FlushNoGap, InterleavedPcm, Mode, MonoPcm};
+use std::io::Write;
+use std::process;
+use std::str::FromStr;
+use std::thread;
+use std::time::Duration;
+
+const WAVE_FORMAT_PCM: u16 = 1;
+
+/// The audio format and PCM data of a WAV file.
+pub struct Wav<'a> {
+  pub format: u16,
+  pub channels: u16,
+  pub sample_rate: u32,
+  pub bits_per_sample: u16,
+  pub data: &'a [u8]
+}
+
+impl<'a> Wav<'a> {
+  /// Parse a RIFF WAVE file.
+  ///
+  /// The size of the data chunk is not trusted because TTS engines writing
+  /// to a pipe can not seek back to fill it in.
+  pub fn parse(bytes: &'a [u8]) -> Result<Self> {
+    if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+      return Err("Not a RIFF WAVE file".into());
+    }
+    let mut fmt = None;
+    let mut offset = 12;
+    while offset + 8 <= bytes.len() {
+      let id = &bytes[offset..offset + 4];
+      let size = u32_at(bytes, offset + 4) as usize;
+      let body = offset + 8;
+      match id {
+        b"fmt " if body + 16 <= bytes.len() => {
+          fmt = Some((
+            u16_at(bytes, body),
+            u16_at(bytes, body + 2),
+            u32_at(bytes, body + 4),
+            u16_at(bytes, body + 14)
+          ));
+        }
+        b"data" => {
+          let (format, channels, sample_rate, bits_per_sample) =
+            fmt.ok_or("WAVE data chunk precedes format chunk")?;
+          // Pipes leave the size at 0 or 0xFFFFFFFF, the data runs to the end then.
+          let end = match body.checked_add(size) {
+            Some(end) if size > 0 && end <= bytes.len() => end,
+            _ => bytes.len()
+          };
+          return Ok(Wav {
+            format,
+            channels,
+            sample_rate,
+            bits_per_sample,
+            data: &bytes[body..end]
+          });
+        }
+        _ => {}
+      }
+      offset = body.saturating_add(size + size % 2);
+    }
+    Err("WAVE file has no data chunk".into())
+  }
+
+  pub fn duration(&self) -> Duration {
+    let bytes_per_second = u64::from(self.sample_rate)
+      * u64::from(self.channels)
+      * u64::from(self.bits_per_sample / 8);
+    if bytes_per_second == 0 {
+      return Duration::from_secs(0);
+    }
+    Duration::from_secs_f64(self.data.len() as f64 / bytes_per_second as f64)
+  }
+
+  fn is_pcm16(&self) -> bool {
+    self.format == WAVE_FORMAT_PCM
+      && self.bits_per_sample == 16
+      && (self.channels == 1 || self.channels == 2)
+  }
+
+  fn samples(&self) -> Vec<i16> {
+    self
+      .data
+      .chunks_exact(2)
+      .map(|sample| i16::from_le_bytes([sample[0], sample[1]]))
+      .collect()
+  }
+
+  /// Serialize with correct chunk sizes.
+  fn to_bytes(&self) -> Result<Vec<u8>> {
+    let block_align = self.channels * (self.bits_per_sample / 8);
+    let byte_rate = self.sample_rate * u32::from(block_align);
+    let data_size = u32::try_from(self.data.len())
+      .ok()
+      .filter(|size| *size <= u32::MAX - 36)
+      .ok_or("Audio is too long for a WAV file")?;
+    let mut bytes = Vec::with_capacity(44 + self.data.len());
+    bytes.extend_from_slice(b"RIFF");
+    bytes.extend_from_slice(&(36 + data_size).to_le_bytes());
+    bytes.extend_from_slice(b"WAVEfmt ");
+    bytes.extend_from_slice(&16u32.to_le_bytes());
+    bytes.extend_from_slice(&self.format.to_le_bytes());
+    bytes.extend_from_slice(&self.channels.to_le_bytes());
+    bytes.extend_from_slice(&self.sample_rate.to_le_bytes());
+    bytes.extend_from_slice(&byte_rate.to_le_bytes());
+    bytes.extend_from_slice(&block_align.to_le_bytes());
+    bytes.extend_from_slice(&self.bits_per_sample.to_le_bytes());
+    bytes.extend_from_slice(b"data");
+    bytes.extend_from_slice(&data_size.to_le_bytes());
+    bytes.extend_from_slice(self.data);
+    Ok(bytes)
+  }
+}
+
+fn u16_at(bytes: &[u8], offset: usize) -> u16 {
+  u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+  u32::from_le_bytes([
+    bytes[offset],
+    bytes[offset + 1],
+    bytes[offset + 2],
+    bytes[offset + 3]
+  ])
+}
+
+/// Encoded audio ready to be served to a player.
+pub struct Clip {
+  pub data: Vec<u8>,
+  pub extension: &'static str,
+  pub duration: Duration
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Encoder {
+  /// MP3 encoded in-process with LAME.
+  Mp3,
+  /// WAV with a corrected header, no compression at all.
+  Wav,
+  /// MP3 encoded by an external `ffmpeg` process.
+  Ffmpeg
+}
+
+impl FromStr for Encoder {
+  type Err = crate::Error;
+  fn from_str(s: &str) -> Result<Self> {
+    match s {
+      "mp3" => Ok(Encoder::Mp3),
+      "wav" => Ok(Encoder::Wav),
+      "ffmpeg" => Ok(Encoder::Ffmpeg),
+      _ => Err(format!("Unknown encoder '{}'", s).into())
+    }
+  }
+}
+
+impl Encoder {
+  pub const NAMES: [&'static str; 3] = ["mp3", "wav", "ffmpeg"];
+
+  /// Encode WAV data.
+  ///
+  /// Falls back to `ffmpeg` if the audio format is not supported natively.
+  pub fn encode(self, wav: &[u8]) -> Result<Clip> {
+    let parsed = Wav::parse(wav).chain_err(|| "Failed to parse WAV from TTS engine")?;
+    let duration = parsed.duration();
+    let (data, extension) = match self {
+      Encoder::Mp3 if parsed.is_pcm16() => (lame(&parsed)?, "mp3"),
+      Encoder::Wav => (parsed.to_bytes()?, "wav"),
+      Encoder::Mp3 | Encoder::Ffmpeg => (ffmpeg(wav)?, "mp3")
+    };
+    Ok(Clip {
+      data,
+      extension,
+      duration
+    })
+  }
+}
+
+fn lame(wav: &Wav) -> Result<Vec<u8>> {
+  let mut builder = Builder::new().ok_or("Failed to initialise LAME")?;
+  builder
+    .set_num_channels(wav.channels as u8)
+    .and_then(|_| builder.set_sample_rate(wav.sample_rate))
+    .and_then(|_| builder.set_brate(Bitrate::Kbps96))
+    .and_then(|_| {
+      builder.set_mode(if wav.channels == 1 {
+        Mode::Mono
+      } else {
+        Mode::JointStereo
+      })
+    })
+    .map_err(|e| format!("Failed to configure MP3 encoder: {}", e))?;
+  let mut encoder = builder
+    .build()
+    .map_err(|e| format!("Failed to configure MP3 encoder: {}", e))?;
+  let samples = wav.samples();
+  let frames = samples.len() / usize::from(wav.channels);
+  let mut mp3 = Vec::with_capacity(mp3lame_encoder::max_required_buffer_size(frames));
+  if wav.channels == 1 {
+    encoder.encode_to_vec(MonoPcm(&samples), &mut mp3)
+  } else {
+    encoder.encode_to_vec(InterleavedPcm(&samples), &mut mp3)
+  }
+  .map_err(|e| format!("Failed to encode MP3: {}", e))?;
+  mp3.reserve(7200);
+  encoder
+    .flush_to_vec::<FlushNoGap>(&mut mp3)
+    .map_err(|e| format!("Failed to encode MP3: {}", e))?;
+  Ok(mp3)
+}
+
+fn ffmpeg(wav: &[u8]) -> Result<Vec<u8>> {
+  let mut ffmpeg = process::Command::new("ffmpeg")
+    .args(["-i", "-", "-v", "fatal", "-b:a", "96k", "-f", "mp3", "-"])
+    .stdin(process::Stdio::piped())
+    .stdout(process::Stdio::piped())
+    .spawn()
+    .chain_err(|| "Failed to spawn 'ffmpeg', is it installed?  Try '--encoder mp3'")?;
+  let mut stdin = ffmpeg.stdin.take().unwrap();
+  let wav = wav.to_vec();
+  let writer = thread::spawn(move || stdin.write_all(&wav));
+  let output = ffmpeg.wait_with_output()?;
+  writer.join().map_err(|_| "Failed to write to 'ffmpeg'")??;
+  if !output.status.success() {
+    return Err(format!("'ffmpeg' failed with {}", output.status).into());
+  }
+  Ok(output.stdout)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn chunk(id: &[u8], size: u32, body: &[u8]) -> Vec<u8> {
+    let mut bytes = id.to_vec();
+    bytes.extend_from_slice(&size.to_le_bytes());
+    bytes.extend_from_slice(body);
+    bytes
+  }
+
+  /// 22.05kHz 16-bit mono, as written by espeak.
+  fn fmt() -> Vec<u8> {
+    let mut body = Vec::new();
+    body.extend_from_slice(&WAVE_FORMAT_PCM.to_le_bytes());
+    body.extend_from_slice(&1u16.to_le_bytes());
+    body.extend_from_slice(&22050u32.to_le_bytes());
+    body.extend_from_slice(&44100u32.to_le_bytes());
+    body.extend_from_slice(&2u16.to_le_bytes());
+    body.extend_from_slice(&16u16.to_le_bytes());
+    chunk(b"fmt ", 16, &body)
+  }
+
+  fn riff(size: u32, chunks: &[Vec<u8>]) -> Vec<u8> {
+    let mut bytes = b"RIFF".to_vec();
+    bytes.extend_from_slice(&size.to_le_bytes());
+    bytes.extend_from_slice(b"WAVE");
+    for chunk in chunks {
+      bytes.extend_from_slice(chunk);
+    }
+    bytes
+  }
+
+  #[test]
+  fn parses_format_and_data() {
+    let bytes = riff(44, &[fmt(), chunk(b"data", 8, &[1, 0, 2, 0, 3, 0, 4, 0])]);
+    let wav = Wav::parse(&bytes).unwrap();
+    assert_eq!((wav.format, wav.channels, wav.sample_rate, wav.bits_per_sample), (1, 1, 22050, 16));
+    assert!(wav.is_pcm16());
+    assert_eq!(wav.samples(), vec![1, 2, 3, 4]);
+  }
+
+  #[test]
+  fn ignores_sizes_written_to_a_pipe() {
+    let samples = [1, 0, 2, 0];
+    for size in [0, u32::MAX] {
+      let bytes = riff(size, &[fmt(), chunk(b"data", size, &samples)]);
+      assert_eq!(Wav::parse(&bytes).unwrap().data, &samples);
+    }
+  }
+
+  #[test]
+  fn skips_padded_odd_sized_chunks() {
+    let bytes = riff(0, &[chunk(b"LIST", 3, &[b'a', b'b', b'c', 0]), fmt(), chunk(b"data", 2, &[7, 0])]);
+    assert_eq!(Wav::parse(&bytes).unwrap().data, &[7, 0]);
+  }
+
+  #[test]
+  fn requires_format_before_data() {
+    let bytes = riff(0, &[chunk(b"data", 2, &[7, 0]), fmt()]);
+    assert!(Wav::parse(&bytes).is_err());
+    assert!(Wav::parse(b"RIFF\0\0\0\0WAVE").is_err());
+    assert!(Wav::parse(b"RIFX\0\0\0\0WAVE").is_err());
+  }
+
+  #[test]
+  fn writes_correct_sizes() {
+    let bytes = riff(0, &[fmt(), chunk(b"data", 0, &[1, 0, 2, 0])]);
+    let wav = Wav::parse(&bytes).unwrap();
+    let written = wav.to_bytes().unwrap();
+    assert_eq!(written.len(), 48);
+    assert_eq!(u32_at(&written, 4), 40);
+    assert_eq!(u32_at(&written, 40), 4);
+    assert_eq!(wav.duration(), Duration::from_secs_f64(4.0 / 44100.0));
+  }
+}
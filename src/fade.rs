@@ -0,0 +1,130 @@
+//! Gradual volume changes for groups and players.
+
+use crate::Result;
+use ronor::{Group, Player, Sonos};
+use std::str::FromStr;
+use std::thread;
+use std::time::Duration;
+
+/// Never send volume changes more often than this.
+const MIN_STEP: Duration = Duration::from_millis(250);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Curve {
+  /// Change volume at a constant rate.
+  Linear,
+  /// Change volume quickly at first and slow down towards the end.
+  Logarithmic
+}
+
+impl FromStr for Curve {
+  type Err = crate::Error;
+  fn from_str(s: &str) -> Result<Self> {
+    match s {
+      "linear" => Ok(Curve::Linear),
+      "logarithmic" => Ok(Curve::Logarithmic),
+      _ => Err(format!("Unknown curve '{}'", s).into())
+    }
+  }
+}
+
+impl Curve {
+  pub const NAMES: [&'static str; 2] = ["linear", "logarithmic"];
+
+  /// Map progress in time (0.0 to 1.0) to progress in volume.
+  fn apply(self, t: f64) -> f64 {
+    match self {
+      Curve::Linear => t,
+      Curve::Logarithmic => (1.0 + 9.0 * t).log10()
+    }
+  }
+}
+
+#[derive(Clone, Copy)]
+pub enum Target<'a> {
+  Group(&'a Group),
+  Player(&'a Player)
+}
+
+impl Target<'_> {
+  pub fn name(&self) -> &str {
+    match self {
+      Target::Group(group) => &group.name,
+      Target::Player(player) => &player.name
+    }
+  }
+
+  pub fn volume(&self, sonos: &mut Sonos) -> Result<u8> {
+    Ok(match self {
+      Target::Group(group) => sonos.get_group_volume(group)?.volume,
+      Target::Player(player) => sonos.get_player_volume(player)?.volume
+    })
+  }
+
+  pub fn set_volume(&self, sonos: &mut Sonos, volume: u8) -> Result<()> {
+    match self {
+      Target::Group(group) => sonos.set_group_volume(group, volume),
+      Target::Player(player) => sonos.set_player_volume(player, volume)
+    }?;
+    Ok(())
+  }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Outcome {
+  Completed,
+  /// Someone else changed the volume to the given value.
+  Interrupted(u8),
+  /// The caller asked to stop.
+  Cancelled
+}
+
+/// Ramp the volume of `target` to `to` within `over`.
+///
+/// Stops early if the volume is changed by someone else during the ramp.
+pub fn fade(
+  sonos: &mut Sonos,
+  target: Target,
+  to: u8,
+  over: Duration,
+  curve: Curve
+) -> Result<Outcome> {
+  fade_while(sonos, target, to, over, curve, || true)
+}
+
+/// Like [`fade`], but checks `keep_going` before every step.
+pub fn fade_while<F: FnMut() -> bool>(
+  sonos: &mut Sonos,
+  target: Target,
+  to: u8,
+  over: Duration,
+  curve: Curve,
+  mut keep_going: F
+) -> Result<Outcome> {
+  let from = target.volume(sonos)?;
+  if from == to {
+    return Ok(Outcome::Completed);
+  }
+  let distance = i32::from(to) - i32::from(from);
+  let max_steps = (over.as_millis() / MIN_STEP.as_millis()).max(1) as u32;
+  let steps = (distance.unsigned_abs()).clamp(1, max_steps);
+  let interval = over / steps;
+  let mut expected = from;
+  for step in 1..=steps {
+    thread::sleep(interval);
+    if !keep_going() {
+      return Ok(Outcome::Cancelled);
+    }
+    let current = target.volume(sonos)?;
+    if current != expected {
+      return Ok(Outcome::Interrupted(current));
+    }
+    let progress = curve.apply(f64::from(step) / f64::from(steps));
+    let volume = (f64::from(from) + f64::from(distance) * progress).round() as u8;
+    if volume != current {
+      target.set_volume(sonos, volume)?;
+    }
+    expected = volume;
+  }
+  Ok(Outcome::Completed)
+}
@@ -0,0 +1,59 @@
+//! A minimal HTTP server which makes local files reachable by players.
+
+use crate::{Result, ResultExt};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::thread;
+use tiny_http::{Header, Response, Server};
+use url::Url;
+
+pub struct FileServer {
+  base: Url
+}
+
+impl FileServer {
+  /// Serve the files in `directory` on `address` (`HOST:PORT`) from a
+  /// background thread.
+  ///
+  /// `HOST` has to be an address the players can reach.
+  pub fn start(address: &str, directory: &Path) -> Result<Self> {
+    let base = Url::parse(&format!("http://{}/", address))
+      .chain_err(|| format!("Invalid address to serve on: '{}'", address))?;
+    let server = Server::http(address)
+      .map_err(|e| format!("Failed to listen on {}: {}", address, e))?;
+    let directory = directory.to_path_buf();
+    thread::spawn(move || {
+      for request in server.incoming_requests() {
+        let _ = match file(&directory, request.url()) {
+          Some((path, content_type)) => match File::open(path) {
+            Ok(file) => request.respond(
+              Response::from_file(file).with_header(
+                Header::from_bytes(&b"Content-Type"[..], content_type.as_bytes()).unwrap()
+              )
+            ),
+            Err(_) => request.respond(Response::empty(404))
+          },
+          None => request.respond(Response::empty(404))
+        };
+      }
+    });
+    Ok(FileServer { base })
+  }
+
+  pub fn url(&self, file_name: &str) -> Result<Url> {
+    Ok(self.base.join(file_name)?)
+  }
+}
+
+fn file(directory: &Path, url: &str) -> Option<(PathBuf, &'static str)> {
+  let name = url.strip_prefix('/')?;
+  if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '.') {
+    return None;
+  }
+  let content_type = match Path::new(name).extension()?.to_str()? {
+    "mp3" => "audio/mpeg",
+    "wav" => "audio/wav",
+    _ => return None
+  };
+  Some((directory.join(name), content_type))
+}
@@ -0,0 +1,418 @@
+//! A local HTTP gateway which offers the controls of a household as JSON.
+//!
+//! - `GET /` is a remote control page for browsers, built on the routes below.
+//! - `GET /groups`, `GET /players`, `GET /favorites` and `GET /playlists`
+//!   list what there is.
+//! - `GET /groups/{name}` describes a group and what it is playing.
+//! - `POST /groups/{name}/{action}` with `play`, `pause`, `stop`, `toggle`,
+//!   `next` or `previous` controls playback.
+//! - `POST /groups/{name}/favorite` and `POST /groups/{name}/playlist` load
+//!   `{"name": "...", "play": true}`.
+//! - `GET` and `PUT /groups/{name}/volume` and `/players/{name}/volume`
+//!   take `{"volume": 0..100}` or `{"delta": -100..100}`, and `{"muted": bool}`.
+//! - `POST /players/{name}/speak` speaks
+//!   `{"text": "...", "language": "de", "ssml": false, "volume": 0..100}`.
+//!
+//! Requests are handled one after another by the thread which owns the
+//! connection to Sonos, so `speak` holds up other requests until the last
+//! clip has been queued.
+
+use crate::cache::ClipCache;
+use crate::now_playing::fields;
+use crate::subcmds::speak;
+use crate::{Error, ErrorKind, Result};
+use percent_encoding::percent_decode_str;
+use ronor::{Group, Household, HouseholdSnapshot, PlaybackState, Player, Sonos};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::io::Read;
+use tiny_http::{Header, Method, Request, Response, Server};
+
+type Reply = (u16, Value);
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct VolumeChange {
+  volume: Option<u8>,
+  delta: Option<i8>,
+  muted: Option<bool>
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LoadRequest {
+  name: String,
+  #[serde(default = "yes")]
+  play: bool
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SpeakRequest {
+  text: String,
+  language: Option<String>,
+  #[serde(default)]
+  ssml: bool,
+  volume: Option<u8>
+}
+
+const REMOTE: &str = include_str!("../static/remote.html");
+
+/// Request bodies larger than this are rejected, no route needs that much.
+const MAX_BODY: u64 = 64 * 1024;
+
+fn yes() -> bool {
+  true
+}
+
+/// Answer requests on `address` (`HOST:PORT`) forever.
+///
+/// If `token` is given, every request has to carry it as a bearer token.
+pub fn serve(
+  sonos: &mut Sonos,
+  household: &Household,
+  address: &str,
+  token: Option<&str>
+) -> Result<()> {
+  let server = Server::http(address)
+    .map_err(|e| format!("Failed to listen on {}: {}", address, e))?;
+  let cache = ClipCache::open(100 * 1024 * 1024)?;
+  println!("Listening on http://{}/", address);
+  for mut request in server.incoming_requests() {
+    // The page holds no secrets, it asks for the token itself.
+    if request.url() == "/" && *request.method() == Method::Get {
+      let page = Response::from_string(REMOTE)
+        .with_header(header("Content-Type", "text/html; charset=utf-8"));
+      let _ = request.respond(page);
+      continue;
+    }
+    let (status, body) = if !same_origin(&request) {
+      (403, json!({ "error": "Cross-origin requests are not allowed" }))
+    } else if authorized(&request, token) {
+      handle(sonos, household, &cache, &mut request).unwrap_or_else(|e| {
+        (status(&e), json!({ "error": e.to_string() }))
+      })
+    } else {
+      (401, json!({ "error": "Missing or wrong bearer token" }))
+    };
+    let response = match body {
+      Value::Null => Response::from_string(String::new()).with_status_code(status),
+      body => Response::from_string(body.to_string())
+        .with_status_code(status)
+        .with_header(header("Content-Type", "application/json"))
+    };
+    let _ = request.respond(response);
+  }
+  Ok(())
+}
+
+fn authorized(request: &Request, token: Option<&str>) -> bool {
+  match token {
+    None => true,
+    Some(token) => request.headers().iter().any(|header| {
+      header.field.equiv("Authorization")
+        && header
+          .value
+          .as_str()
+          .strip_prefix("Bearer ")
+          .is_some_and(|given| constant_time_eq(given.as_bytes(), token.as_bytes()))
+    })
+  }
+}
+
+/// Whether a request which changes something comes from the remote control
+/// page or a client which is not a browser.
+///
+/// Browsers send simple cross-origin requests without asking first, so
+/// without this any web page could control the household.  They always
+/// include `Origin` then, other clients do not.
+fn same_origin(request: &Request) -> bool {
+  if matches!(request.method(), Method::Get | Method::Head) {
+    return true;
+  }
+  match (header_value(request, "Origin"), header_value(request, "Host")) {
+    (None, _) => true,
+    (Some(origin), Some(host)) => origin.strip_prefix("http://") == Some(host),
+    (Some(_), None) => false
+  }
+}
+
+/// Compare without stopping at the first difference, so the time taken
+/// does not tell how much of a guessed token was right.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+  a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+fn handle(
+  sonos: &mut Sonos,
+  household: &Household,
+  cache: &ClipCache,
+  request: &mut Request
+) -> Result<Reply> {
+  let path = request.url().split('?').next().unwrap_or_default().to_string();
+  let segments: Vec<String> = path
+    .split('/')
+    .filter(|segment| !segment.is_empty())
+    .map(|segment| percent_decode_str(segment).decode_utf8_lossy().to_string())
+    .collect();
+  let segments: Vec<&str> = segments.iter().map(String::as_str).collect();
+  let method = request.method().clone();
+  if request.body_length().is_some_and(|length| length as u64 > MAX_BODY) {
+    return Ok(too_large());
+  }
+  let mut body = Vec::new();
+  request.as_reader().take(MAX_BODY + 1).read_to_end(&mut body)?;
+  if body.len() as u64 > MAX_BODY {
+    return Ok(too_large());
+  }
+  let body = String::from_utf8(body).map_err(|_| "Request body is not valid UTF-8")?;
+  match (&method, segments.as_slice()) {
+    (Method::Get, ["favorites"]) => {
+      let favorites = sonos.get_favorites(household)?;
+      let names: Vec<&String> = favorites.items.iter().map(|favorite| &favorite.name).collect();
+      return Ok((200, json!(names)));
+    }
+    (Method::Get, ["playlists"]) => {
+      let playlists = sonos.get_playlists(household)?;
+      let names: Vec<&String> = playlists.playlists.iter().map(|playlist| &playlist.name).collect();
+      return Ok((200, json!(names)));
+    }
+    _ => {}
+  }
+  let targets = HouseholdSnapshot::from(sonos.get_groups(household)?);
+  match (&method, segments.as_slice()) {
+    (Method::Get, ["groups"]) => {
+      let groups: Vec<Value> =
+        targets.groups().iter().map(|group| describe_group(&targets, group)).collect();
+      Ok((200, json!(groups)))
+    }
+    (Method::Get, ["players"]) => {
+      let players: Vec<Value> =
+        targets.players().iter().map(|player| describe_player(&targets, player)).collect();
+      Ok((200, json!(players)))
+    }
+    (_, ["groups", name, rest @ ..]) => {
+      let group = targets
+        .group_by_name(name)
+        .ok_or_else(|| ErrorKind::UnknownGroup(name.to_string()))?;
+      group_route(sonos, household, &targets, group, &method, rest, &body)
+    }
+    (_, ["players", name, rest @ ..]) => {
+      let player = targets
+        .player_by_name(name)
+        .ok_or_else(|| ErrorKind::UnknownPlayer(name.to_string()))?;
+      player_route(sonos, cache, player, &method, rest, &body)
+    }
+    _ => Ok(not_found())
+  }
+}
+
+fn group_route(
+  sonos: &mut Sonos,
+  household: &Household,
+  targets: &HouseholdSnapshot,
+  group: &Group,
+  method: &Method,
+  rest: &[&str],
+  body: &str
+) -> Result<Reply> {
+  match (method, rest) {
+    (Method::Get, []) => {
+      let mut description = describe_group(targets, group);
+      if group.playback_state != PlaybackState::Idle {
+        let playback_status = sonos.get_playback_status(group)?;
+        let metadata_status = sonos.get_metadata_status(group)?;
+        description["nowPlaying"] = json!(fields(group, &playback_status, &metadata_status));
+      }
+      Ok((200, description))
+    }
+    (Method::Post, [action]) => {
+      match *action {
+        "play" => sonos.play(group)?,
+        "pause" => sonos.pause_or_stop(group)?,
+        "toggle" => match group.playback_state {
+          PlaybackState::Playing | PlaybackState::Buffering => sonos.pause_or_stop(group)?,
+          _ => sonos.play(group)?
+        },
+        "stop" => {
+          if group.playback_state != PlaybackState::Idle {
+            sonos.stop(group)?
+          }
+        }
+        "next" => sonos.skip_to_next_track(group)?,
+        "previous" => sonos.skip_to_previous_track(group)?,
+        "favorite" => {
+          let request: LoadRequest = match parse(body) {
+            Ok(request) => request,
+            Err(reply) => return Ok(reply)
+          };
+          let favorite = sonos
+            .get_favorites(household)?
+            .items
+            .into_iter()
+            .find(|favorite| favorite.name == request.name)
+            .ok_or(ErrorKind::UnknownFavorite(request.name))?;
+          sonos.load_favorite(group, &favorite, request.play, None)?;
+        }
+        "playlist" => {
+          let request: LoadRequest = match parse(body) {
+            Ok(request) => request,
+            Err(reply) => return Ok(reply)
+          };
+          let playlist = sonos
+            .get_playlists(household)?
+            .playlists
+            .into_iter()
+            .find(|playlist| playlist.name == request.name)
+            .ok_or(ErrorKind::UnknownPlaylist(request.name))?;
+          sonos.load_playlist(group, &playlist, request.play, None)?;
+        }
+        _ => return Ok(not_found())
+      }
+      Ok((204, Value::Null))
+    }
+    (Method::Get, ["volume"]) => {
+      let volume = sonos.get_group_volume(group)?;
+      Ok((200, json!({ "volume": volume.volume, "muted": volume.muted, "fixed": volume.fixed })))
+    }
+    (Method::Put, ["volume"]) => {
+      let change: VolumeChange = match parse(body) {
+        Ok(change) => change,
+        Err(reply) => return Ok(reply)
+      };
+      match (change.volume, change.delta) {
+        (Some(_), Some(_)) => return Ok(bad_request("Give either volume or delta")),
+        (Some(volume), None) if volume > 100 => return Ok(bad_request("Volume exceeds 100")),
+        (Some(volume), None) => sonos.set_group_volume(group, volume)?,
+        (None, Some(delta)) => sonos.set_relative_group_volume(group, delta)?,
+        (None, None) => {}
+      }
+      if let Some(muted) = change.muted {
+        sonos.set_group_mute(group, muted)?;
+      }
+      Ok((204, Value::Null))
+    }
+    _ => Ok(not_found())
+  }
+}
+
+fn player_route(
+  sonos: &mut Sonos,
+  cache: &ClipCache,
+  player: &Player,
+  method: &Method,
+  rest: &[&str],
+  body: &str
+) -> Result<Reply> {
+  match (method, rest) {
+    (Method::Get, ["volume"]) => {
+      let volume = sonos.get_player_volume(player)?;
+      Ok((200, json!({ "volume": volume.volume, "muted": volume.muted, "fixed": volume.fixed })))
+    }
+    (Method::Put, ["volume"]) => {
+      let change: VolumeChange = match parse(body) {
+        Ok(change) => change,
+        Err(reply) => return Ok(reply)
+      };
+      match (change.volume, change.delta) {
+        (Some(_), Some(_)) => return Ok(bad_request("Give either volume or delta")),
+        (Some(volume), None) if volume > 100 => return Ok(bad_request("Volume exceeds 100")),
+        (Some(volume), None) => sonos.set_player_volume(player, volume)?,
+        (None, Some(delta)) => sonos.set_relative_player_volume(player, delta)?,
+        (None, None) => {}
+      }
+      if let Some(muted) = change.muted {
+        sonos.set_player_mute(player, muted)?;
+      }
+      Ok((204, Value::Null))
+    }
+    (Method::Post, ["speak"]) => {
+      let request: SpeakRequest = match parse(body) {
+        Ok(request) => request,
+        Err(reply) => return Ok(reply)
+      };
+      if request.volume.is_some_and(|volume| volume > 100) {
+        return Ok(bad_request("Volume exceeds 100"));
+      }
+      let options = speak::Options {
+        clip_volume: request.volume,
+        cache: Some(cache),
+        ..speak::Options::default()
+      };
+      let segments = speak::segments(request.text, request.language, request.ssml)?;
+      speak::speak(sonos, player, segments, &options)?;
+      Ok((204, Value::Null))
+    }
+    _ => Ok(not_found())
+  }
+}
+
+fn describe_group(targets: &HouseholdSnapshot, group: &Group) -> Value {
+  json!({
+    "id": group.id.to_string(),
+    "name": group.name,
+    "coordinator": targets.coordinator(group).map(|player| &player.name),
+    "players": targets.members(group).map(|player| &player.name).collect::<Vec<_>>(),
+    "playbackState": match group.playback_state {
+      PlaybackState::Idle => "idle",
+      PlaybackState::Paused => "paused",
+      PlaybackState::Buffering => "buffering",
+      PlaybackState::Playing => "playing"
+    }
+  })
+}
+
+fn describe_player(targets: &HouseholdSnapshot, player: &Player) -> Value {
+  let capabilities: Vec<String> = player
+    .capabilities
+    .iter()
+    .map(|capability| format!("{:?}", capability))
+    .collect();
+  json!({
+    "id": player.id.to_string(),
+    "name": player.name,
+    "group": targets.group_of(&player.id).map(|group| &group.name),
+    "capabilities": capabilities
+  })
+}
+
+fn parse<'a, T: Deserialize<'a>>(body: &'a str) -> std::result::Result<T, Reply> {
+  serde_json::from_str(body).map_err(|e| bad_request(&format!("Invalid request body: {}", e)))
+}
+
+fn bad_request(message: &str) -> Reply {
+  (400, json!({ "error": message }))
+}
+
+fn not_found() -> Reply {
+  (404, json!({ "error": "No such route" }))
+}
+
+fn too_large() -> Reply {
+  (413, json!({ "error": format!("Request body exceeds {} bytes", MAX_BODY) }))
+}
+
+fn status(e: &Error) -> u16 {
+  match e.kind() {
+    ErrorKind::UnknownFavorite(_)
+    | ErrorKind::UnknownGroup(_)
+    | ErrorKind::UnknownPlayer(_)
+    | ErrorKind::UnknownPlaylist(_) => 404,
+    ErrorKind::API(ronor::ErrorKind::MissingCapability(_))
+    | ErrorKind::API(ronor::ErrorKind::UnavailablePlaybackAction(_)) => 409,
+    ErrorKind::API(_) | ErrorKind::Reqwest(_) => 502,
+    _ => 500
+  }
+}
+
+fn header_value<'a>(request: &'a Request, field: &'static str) -> Option<&'a str> {
+  request
+    .headers()
+    .iter()
+    .find(|header| header.field.equiv(field))
+    .map(|header| header.value.as_str())
+}
+
+fn header(field: &str, value: &str) -> Header {
+  Header::from_bytes(field.as_bytes(), value.as_bytes()).unwrap()
+}
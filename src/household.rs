@@ -0,0 +1,212 @@
+//! An indexed view of the groups and players in a household.
+
+use crate::{Capability, Group, GroupId, Groups, Player, PlayerId};
+use std::collections::HashMap;
+
+/// The groups and players of a household, as returned by
+/// [`Sonos::get_groups`](crate::Sonos::get_groups), indexed by ID and name.
+///
+/// Should several groups or players share a name, lookups by name return
+/// the first one, just like a linear search would.
+pub struct HouseholdSnapshot {
+  groups: Groups,
+  players_by_id: HashMap<PlayerId, usize>,
+  players_by_name: HashMap<String, usize>,
+  groups_by_id: HashMap<GroupId, usize>,
+  groups_by_name: HashMap<String, usize>,
+  groups_by_coordinator: HashMap<PlayerId, usize>,
+  groups_by_member: HashMap<PlayerId, usize>
+}
+
+impl From<Groups> for HouseholdSnapshot {
+  fn from(groups: Groups) -> Self {
+    let mut players_by_id = HashMap::new();
+    let mut players_by_name = HashMap::new();
+    for (index, player) in groups.players.iter().enumerate() {
+      players_by_id.entry(player.id.clone()).or_insert(index);
+      players_by_name.entry(player.name.clone()).or_insert(index);
+    }
+    let mut groups_by_id = HashMap::new();
+    let mut groups_by_name = HashMap::new();
+    let mut groups_by_coordinator = HashMap::new();
+    let mut groups_by_member = HashMap::new();
+    for (index, group) in groups.groups.iter().enumerate() {
+      groups_by_id.entry(group.id.clone()).or_insert(index);
+      groups_by_name.entry(group.name.clone()).or_insert(index);
+      groups_by_coordinator
+        .entry(group.coordinator_id.clone())
+        .or_insert(index);
+      for player_id in group.player_ids.iter() {
+        groups_by_member.entry(player_id.clone()).or_insert(index);
+      }
+    }
+    HouseholdSnapshot {
+      groups,
+      players_by_id,
+      players_by_name,
+      groups_by_id,
+      groups_by_name,
+      groups_by_coordinator,
+      groups_by_member
+    }
+  }
+}
+
+impl HouseholdSnapshot {
+  pub fn groups(&self) -> &[Group] {
+    &self.groups.groups
+  }
+
+  pub fn players(&self) -> &[Player] {
+    &self.groups.players
+  }
+
+  /// Whether Sonos only returned part of the household.
+  pub fn is_partial(&self) -> bool {
+    self.groups.partial
+  }
+
+  pub fn player(&self, id: &PlayerId) -> Option<&Player> {
+    self.players_by_id.get(id).map(|index| &self.groups.players[*index])
+  }
+
+  pub fn player_by_name(&self, name: &str) -> Option<&Player> {
+    self.players_by_name.get(name).map(|index| &self.groups.players[*index])
+  }
+
+  pub fn group(&self, id: &GroupId) -> Option<&Group> {
+    self.groups_by_id.get(id).map(|index| &self.groups.groups[*index])
+  }
+
+  pub fn group_by_name(&self, name: &str) -> Option<&Group> {
+    self.groups_by_name.get(name).map(|index| &self.groups.groups[*index])
+  }
+
+  /// The group coordinated by `player`.
+  pub fn coordinated_by(&self, player: &PlayerId) -> Option<&Group> {
+    self
+      .groups_by_coordinator
+      .get(player)
+      .map(|index| &self.groups.groups[*index])
+  }
+
+  /// The group `player` is a member of.
+  pub fn group_of(&self, player: &PlayerId) -> Option<&Group> {
+    self.groups_by_member.get(player).map(|index| &self.groups.groups[*index])
+  }
+
+  /// The player coordinating `group`.
+  pub fn coordinator(&self, group: &Group) -> Option<&Player> {
+    self.player(&group.coordinator_id)
+  }
+
+  /// The players in `group`, in the order Sonos lists them.
+  ///
+  /// Members which are not known to the household are left out.
+  pub fn members<'a>(&'a self, group: &'a Group) -> impl Iterator<Item = &'a Player> + 'a {
+    group.player_ids.iter().filter_map(move |id| self.player(id))
+  }
+
+  /// All players which have `capability`.
+  pub fn with_capability(&self, capability: Capability) -> impl Iterator<Item = &Player> {
+    self
+      .groups
+      .players
+      .iter()
+      .filter(move |player| player.capabilities.contains(&capability))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use serde_json::json;
+
+  fn player(id: &str, name: &str, capabilities: &[&str]) -> serde_json::Value {
+    json!({
+      "isUnregistered": false,
+      "apiVersion": "1.1.0",
+      "deviceIds": [id],
+      "id": id,
+      "minApiVersion": "1.1.0",
+      "name": name,
+      "softwareVersion": "1.0",
+      "capabilities": capabilities,
+      "websocketUrl": "wss://localhost"
+    })
+  }
+
+  fn group(id: &str, name: &str, players: &[&str]) -> serde_json::Value {
+    json!({
+      "coordinatorId": players[0],
+      "id": id,
+      "playbackState": "PLAYBACK_STATE_IDLE",
+      "playerIds": players,
+      "name": name
+    })
+  }
+
+  /// Two groups, one of them coordinated by a player with the same name as
+  /// another one, and a member which is not listed as a player.
+  fn household() -> HouseholdSnapshot {
+    let groups: Groups = serde_json::from_value(json!({
+      "groups": [
+        group("G1", "Kitchen + 1", &["P1", "P2", "P9"]),
+        group("G2", "Bath", &["P3"])
+      ],
+      "players": [
+        player("P1", "Kitchen", &["PLAYBACK"]),
+        player("P2", "Dining", &["PLAYBACK", "VOICE"]),
+        player("P3", "Kitchen", &["PLAYBACK"])
+      ],
+      "partial": false
+    }))
+    .unwrap();
+    HouseholdSnapshot::from(groups)
+  }
+
+  fn id(id: &str) -> PlayerId {
+    PlayerId::new(id.to_string())
+  }
+
+  #[test]
+  fn looks_up_by_name() {
+    let household = household();
+    assert_eq!(household.group_by_name("Bath").unwrap().id, GroupId::new(String::from("G2")));
+    assert!(household.group_by_name("Kitchen").is_none());
+    assert_eq!(household.player_by_name("Dining").unwrap().id, id("P2"));
+    assert!(household.player_by_name("Garage").is_none());
+  }
+
+  #[test]
+  fn duplicate_names_find_the_first() {
+    assert_eq!(household().player_by_name("Kitchen").unwrap().id, id("P1"));
+  }
+
+  #[test]
+  fn finds_groups_of_players() {
+    let household = household();
+    assert_eq!(household.group_of(&id("P2")).unwrap().name, "Kitchen + 1");
+    assert_eq!(household.group_of(&id("P3")).unwrap().name, "Bath");
+    assert!(household.group_of(&id("P7")).is_none());
+    assert_eq!(household.coordinated_by(&id("P1")).unwrap().name, "Kitchen + 1");
+    assert!(household.coordinated_by(&id("P2")).is_none());
+  }
+
+  #[test]
+  fn members_skip_unknown_players() {
+    let household = household();
+    let group = household.group_by_name("Kitchen + 1").unwrap();
+    let members: Vec<&PlayerId> = household.members(group).map(|player| &player.id).collect();
+    assert_eq!(members, vec![&id("P1"), &id("P2")]);
+    assert_eq!(household.coordinator(group).unwrap().name, "Kitchen");
+  }
+
+  #[test]
+  fn filters_by_capability() {
+    let household = household();
+    let players: Vec<&str> =
+      household.with_capability(Capability::Voice).map(|player| player.name.as_str()).collect();
+    assert_eq!(players, vec!["Dining"]);
+  }
+}
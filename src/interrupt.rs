@@ -0,0 +1,82 @@
+//! Interrupt whatever a group is playing and resume it afterwards.
+//!
+//! Audio clips are normally mixed over the current content with ducking.
+//! Interrupting pauses the content instead, so an announcement plays alone
+//! at exactly the volume it was given.
+
+use crate::Result;
+use ronor::{Container, Group, GroupVolume, PlaybackState, PlaybackStatus, Sonos};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Time to allow a player for fetching a clip before it starts playing.
+const LATENCY: Duration = Duration::from_millis(750);
+
+/// What a group was playing before it was interrupted.
+pub struct PausedPlayback {
+  status: PlaybackStatus,
+  volume: GroupVolume,
+  container: Option<Container>
+}
+
+impl PausedPlayback {
+  /// Remember the state of `group` and pause or stop it if it is playing.
+  pub fn pause(sonos: &mut Sonos, group: &Group) -> Result<Self> {
+    let paused = PausedPlayback {
+      status: sonos.get_playback_status(group)?,
+      volume: sonos.get_group_volume(group)?,
+      container: sonos.get_metadata_status(group)?.container
+    };
+    if paused.was_playing() {
+      sonos.pause_or_stop(group)?;
+    }
+    Ok(paused)
+  }
+
+  fn was_playing(&self) -> bool {
+    matches!(
+      self.status.playback_state,
+      PlaybackState::Playing | PlaybackState::Buffering
+    ) && (self.status.available_playback_actions.can_pause
+      || self.status.available_playback_actions.can_stop)
+  }
+
+  /// Wait until `end`, then bring back volume and content.
+  ///
+  /// If someone loaded different content in the meantime, it is left alone.
+  pub fn resume_at(self, sonos: &mut Sonos, group: &Group, end: Instant) -> Result<()> {
+    if let Some(remaining) = (end + LATENCY).checked_duration_since(Instant::now()) {
+      thread::sleep(remaining);
+    }
+    if !self.volume.fixed {
+      sonos.set_group_volume(group, self.volume.volume)?;
+    }
+    sonos.set_group_mute(group, self.volume.muted)?;
+    let container = sonos.get_metadata_status(group)?.container;
+    if !same_container(self.container.as_ref(), container.as_ref()) {
+      return Ok(());
+    }
+    if self.was_playing() {
+      if self.status.available_playback_actions.can_seek {
+        sonos.seek(
+          group,
+          u128::try_from(self.status.position_millis).unwrap_or_default(),
+          self.status.item_id.as_ref()
+        )?;
+      }
+      sonos.play(group)?;
+    }
+    Ok(())
+  }
+}
+
+fn same_container(a: Option<&Container>, b: Option<&Container>) -> bool {
+  match (a, b) {
+    (Some(a), Some(b)) => {
+      a.name == b.name
+        && a.id.as_ref().map(|id| &id.object_id) == b.id.as_ref().map(|id| &id.object_id)
+    }
+    (None, None) => true,
+    _ => false
+  }
+}
@@ -14,12 +14,21 @@ use std::fs::{read_to_string, write};
 use std::str::FromStr;
 use url::Url;
 
+pub mod household;
+pub mod watch;
+
+pub use household::HouseholdSnapshot;
+
 error_chain! {
   errors {
     MissingCapability(c: Capability) {
       description("missing capability")
       display("Player is missing the {:?} capability", c)
     }
+    UnavailablePlaybackAction(action: &'static str) {
+      description("playback action not available")
+      display("Playback action {} is not available for the current content", action)
+    }
     UnknownPlayerId {
       description("PlayerId is unknown")
       display("Failed to find PlayerId")
@@ -75,7 +84,7 @@ fn oauth2(
 
 macro_rules! ids {
   ($name:ident) => {
-    #[derive (Clone, Debug, Deserialize, PartialEq, Serialize)]
+    #[derive (Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
     pub struct $name(String);
 
     impl $name {
@@ -97,7 +106,8 @@ ids!(
   PlayerId,
   FavoriteId,
   PlaylistId,
-  AudioClipId
+  AudioClipId,
+  SessionId
 );
 
 #[derive(Debug, Deserialize, PartialEq, Serialize)]
@@ -145,7 +155,7 @@ pub enum Capability {
   Hdmi
 }
 
-#[derive(Debug, Deserialize, PartialEq)]
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
 pub enum PlaybackState {
   /// Playback is not playing or paused, such as when the queue is empty
   /// or a source cannot be paused (such as streaming radio).
@@ -221,7 +231,7 @@ pub struct Groups {
 }
 
 /// Describes one group in a household.
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize)]
 #[serde(deny_unknown_fields)]
 #[serde(rename_all = "camelCase")]
 pub struct Group {
@@ -262,6 +272,12 @@ pub struct ModifiedGroup {
   pub name: String,
 }
 
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct GroupInfo {
+  group: ModifiedGroup,
+}
+
 /// Describes one logical speaker in a household.
 /// A logical speaker could be a single stand-alone device or a set of bonded
 /// devices. For example, two players bonded as a stereo pair, two
@@ -321,6 +337,25 @@ pub struct AudioClip {
   player_id: Option<PlayerId>,
 }
 
+#[derive(Debug, Deserialize, PartialEq)]
+pub enum SessionState {
+  /// The session is connected to a group and can control it.
+  #[serde(rename = "SESSION_STATE_CONNECTED")]
+  Connected,
+}
+
+/// Describes a playback session, a group under the control of an app.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionStatus {
+  pub session_id: SessionId,
+  pub session_state: SessionState,
+  /// True if a new session was created, false if an existing one was joined.
+  pub session_created: bool,
+  pub custom_data: Option<String>,
+}
+
 #[derive(Debug, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct GroupVolume {
@@ -1072,6 +1107,42 @@ impl Sonos {
     Ok(())
   }
 
+  /// See Sonos API documentation for [stop]
+  ///
+  /// Meant for content which can not be paused like live radio, check
+  /// [`AvailablePlaybackActions::can_stop`] first.
+  ///
+  /// [stop]: https://developer.sonos.com/reference/control-api/playback/stop/
+  pub fn stop(&mut self, group: &Group) -> Result<()> {
+    self.maybe_refresh(|client| {
+      client
+        .post(control_v1!("groups/{}/playback/stop", group.id))
+        .header("Content-Type", "application/json")
+    })?;
+    Ok(())
+  }
+
+  /// Pause `group`, or stop it if what is playing can not be paused.
+  ///
+  /// Groups which are not playing are left alone.
+  pub fn pause_or_stop(&mut self, group: &Group) -> Result<()> {
+    let status = self.get_playback_status(group)?;
+    let actions = &status.available_playback_actions;
+    match status.playback_state {
+      PlaybackState::Idle | PlaybackState::Paused => {}
+      PlaybackState::Playing | PlaybackState::Buffering => {
+        if actions.can_pause {
+          self.pause(group)?;
+        } else if actions.can_stop {
+          self.stop(group)?;
+        } else {
+          return Err(ErrorKind::UnavailablePlaybackAction("pause").into());
+        }
+      }
+    }
+    Ok(())
+  }
+
   /// See Sonos API documentation for [togglePlayPause]
   ///
   /// [togglePlayPause]: https://developer.sonos.com/reference/control-api/playback/toggleplaypause/
@@ -1110,6 +1181,69 @@ impl Sonos {
     })?;
     Ok(())
   }
+
+  /// See Sonos API documentation for [setPlayModes]
+  ///
+  /// Only modes which differ from the current ones are changed, and only if
+  /// the current content allows it.
+  ///
+  /// [setPlayModes]: https://developer.sonos.com/reference/control-api/playback/setplaymodes/
+  pub fn set_play_modes(&mut self, group: &Group, play_modes: &PlayModes) -> Result<()> {
+    let status = self.get_playback_status(group)?;
+    let current = &status.play_modes;
+    let actions = &status.available_playback_actions;
+    #[derive(Default, Serialize)]
+    #[serde(rename_all = "camelCase")]
+    struct Changes {
+      #[serde(skip_serializing_if = "Option::is_none")]
+      repeat: Option<bool>,
+      #[serde(skip_serializing_if = "Option::is_none")]
+      repeat_one: Option<bool>,
+      #[serde(skip_serializing_if = "Option::is_none")]
+      crossfade: Option<bool>,
+      #[serde(skip_serializing_if = "Option::is_none")]
+      shuffle: Option<bool>,
+    }
+    let mut changes = Changes::default();
+    for (wanted, current, available, change, action) in [
+      (play_modes.repeat, current.repeat, actions.can_repeat, &mut changes.repeat, "repeat"),
+      (
+        play_modes.repeat_one,
+        current.repeat_one,
+        actions.can_repeat_one,
+        &mut changes.repeat_one,
+        "repeat one",
+      ),
+      (
+        play_modes.crossfade,
+        current.crossfade,
+        actions.can_crossfade,
+        &mut changes.crossfade,
+        "crossfade",
+      ),
+      (play_modes.shuffle, current.shuffle, actions.can_shuffle, &mut changes.shuffle, "shuffle"),
+    ] {
+      if wanted != current {
+        if !available {
+          return Err(ErrorKind::UnavailablePlaybackAction(action).into());
+        }
+        *change = Some(wanted);
+      }
+    }
+    #[derive(Serialize)]
+    #[serde(rename_all = "camelCase")]
+    struct Params {
+      play_modes: Changes,
+    }
+    let params = Params { play_modes: changes };
+    self.maybe_refresh(|client| {
+      client
+        .post(control_v1!("groups/{}/playback/playMode", group.id))
+        .json(&params)
+    })?;
+    Ok(())
+  }
+
   /// See Sonos API documentation for [seek]
   ///
   /// [seek]: https://developer.sonos.com/reference/control-api/playback/seek/
@@ -1355,6 +1489,195 @@ impl Sonos {
     }
   }
 
+  fn session(
+    &mut self,
+    url: &str,
+    app_id: &str,
+    app_context: &str,
+    custom_data: Option<&str>,
+  ) -> Result<SessionStatus> {
+    #[derive(Serialize)]
+    #[serde(rename_all = "camelCase")]
+    struct Params<'a> {
+      app_id: &'a str,
+      app_context: &'a str,
+      custom_data: Option<&'a str>,
+    }
+    let params = Params {
+      app_id,
+      app_context,
+      custom_data,
+    };
+    let response = self.maybe_refresh(|client| client.post(url).json(&params))?;
+    Ok(response.json()?)
+  }
+
+  /// See Sonos API documentation for [createSession]
+  ///
+  /// [createSession]: https://developer.sonos.com/reference/control-api/playbacksession/createsession/
+  pub fn create_session(
+    &mut self,
+    group: &Group,
+    app_id: &str,
+    app_context: &str,
+    custom_data: Option<&str>,
+  ) -> Result<SessionStatus> {
+    self.session(
+      control_v1!("groups/{}/playbackSession", group.id),
+      app_id,
+      app_context,
+      custom_data,
+    )
+  }
+
+  /// See Sonos API documentation for [joinOrCreateSession]
+  ///
+  /// [joinOrCreateSession]: https://developer.sonos.com/reference/control-api/playbacksession/joinorcreatesession/
+  pub fn join_or_create_session(
+    &mut self,
+    group: &Group,
+    app_id: &str,
+    app_context: &str,
+    custom_data: Option<&str>,
+  ) -> Result<SessionStatus> {
+    self.session(
+      control_v1!("groups/{}/playbackSession/joinOrCreate", group.id),
+      app_id,
+      app_context,
+      custom_data,
+    )
+  }
+
+  /// See Sonos API documentation for [joinSession]
+  ///
+  /// [joinSession]: https://developer.sonos.com/reference/control-api/playbacksession/joinsession/
+  pub fn join_session(
+    &mut self,
+    group: &Group,
+    app_id: &str,
+    app_context: &str,
+  ) -> Result<SessionStatus> {
+    self.session(
+      control_v1!("groups/{}/playbackSession/join", group.id),
+      app_id,
+      app_context,
+      None,
+    )
+  }
+
+  /// See Sonos API documentation for [skipToItem]
+  ///
+  /// [skipToItem]: https://developer.sonos.com/reference/control-api/playbacksession/skiptoitem/
+  pub fn skip_to_item(
+    &mut self,
+    session: &SessionStatus,
+    item_id: &str,
+    queue_version: Option<&str>,
+    play_on_completion: bool,
+  ) -> Result<()> {
+    #[derive(Serialize)]
+    #[serde(rename_all = "camelCase")]
+    struct Params<'a> {
+      item_id: &'a str,
+      queue_version: Option<&'a str>,
+      play_on_completion: bool,
+    }
+    let params = Params {
+      item_id,
+      queue_version,
+      play_on_completion,
+    };
+    self.maybe_refresh(|client| {
+      client
+        .post(control_v1!(
+          "playbackSessions/{}/playbackSession/skipToItem",
+          session.session_id
+        ))
+        .json(&params)
+    })?;
+    Ok(())
+  }
+
+  /// See Sonos API documentation for [loadStreamUrl]
+  ///
+  /// [loadStreamUrl]: https://developer.sonos.com/reference/control-api/playbacksession/loadstreamurl/
+  pub fn load_stream_url(
+    &mut self,
+    session: &SessionStatus,
+    stream_url: &Url,
+    station_name: Option<&str>,
+    play_on_completion: bool,
+  ) -> Result<()> {
+    #[derive(Serialize)]
+    struct StationMetadata<'a> {
+      name: &'a str,
+    }
+    #[derive(Serialize)]
+    #[serde(rename_all = "camelCase")]
+    struct Params<'a> {
+      stream_url: &'a str,
+      play_on_completion: bool,
+      station_metadata: Option<StationMetadata<'a>>,
+    }
+    let params = Params {
+      stream_url: stream_url.as_str(),
+      play_on_completion,
+      station_metadata: station_name.map(|name| StationMetadata { name }),
+    };
+    self.maybe_refresh(|client| {
+      client
+        .post(control_v1!(
+          "playbackSessions/{}/playbackSession/loadStreamUrl",
+          session.session_id
+        ))
+        .json(&params)
+    })?;
+    Ok(())
+  }
+
+  /// See Sonos API documentation for [loadCloudQueue]
+  ///
+  /// [loadCloudQueue]: https://developer.sonos.com/reference/control-api/playbacksession/loadcloudqueue/
+  #[allow(clippy::too_many_arguments)]
+  pub fn load_cloud_queue(
+    &mut self,
+    session: &SessionStatus,
+    queue_base_url: &Url,
+    http_authorization: Option<&str>,
+    item_id: Option<&str>,
+    queue_version: Option<&str>,
+    position_millis: Option<u128>,
+    play_on_completion: bool,
+  ) -> Result<()> {
+    #[derive(Serialize)]
+    #[serde(rename_all = "camelCase")]
+    struct Params<'a> {
+      queue_base_url: &'a str,
+      http_authorization: Option<&'a str>,
+      item_id: Option<&'a str>,
+      queue_version: Option<&'a str>,
+      position_millis: Option<u128>,
+      play_on_completion: bool,
+    }
+    let params = Params {
+      queue_base_url: queue_base_url.as_str(),
+      http_authorization,
+      item_id,
+      queue_version,
+      position_millis,
+      play_on_completion,
+    };
+    self.maybe_refresh(|client| {
+      client
+        .post(control_v1!(
+          "playbackSessions/{}/playbackSession/loadCloudQueue",
+          session.session_id
+        ))
+        .json(&params)
+    })?;
+    Ok(())
+  }
+
   /// See Sonos API documentation for [modifyGroupMembers]
   ///
   /// [modifyGroupMembers]: https://developer.sonos.com/reference/control-api/groups/modifygroupmembers/
@@ -1379,11 +1702,57 @@ impl Sonos {
         .post(control_v1!("groups/{}/groups/modifyGroupMembers", group.id))
         .json(&params)
     })?;
-    #[derive(Deserialize)]
-    #[serde(deny_unknown_fields)]
-    struct GroupInfo {
-      group: ModifiedGroup,
+    let group_info: GroupInfo = response.json()?;
+    Ok(group_info.group)
+  }
+
+  /// See Sonos API documentation for [setGroupMembers]
+  ///
+  /// [setGroupMembers]: https://developer.sonos.com/reference/control-api/groups/setgroupmembers/
+  pub fn set_group_members(
+    &mut self,
+    group: &Group,
+    player_ids: &[&PlayerId],
+  ) -> Result<ModifiedGroup> {
+    #[derive(Serialize)]
+    #[serde(rename_all = "camelCase")]
+    struct Params<'a> {
+      player_ids: &'a [&'a PlayerId],
     }
+    let params = Params { player_ids };
+    let response = self.maybe_refresh(|client| {
+      client
+        .post(control_v1!("groups/{}/groups/setGroupMembers", group.id))
+        .json(&params)
+    })?;
+    let group_info: GroupInfo = response.json()?;
+    Ok(group_info.group)
+  }
+
+  /// See Sonos API documentation for [createGroup]
+  ///
+  /// Players are taken out of the groups they are currently part of.
+  ///
+  /// [createGroup]: https://developer.sonos.com/reference/control-api/groups/creategroup/
+  pub fn create_group(
+    &mut self,
+    household: &Household,
+    player_ids: &[&PlayerId],
+  ) -> Result<ModifiedGroup> {
+    #[derive(Serialize)]
+    #[serde(rename_all = "camelCase")]
+    struct Params<'a> {
+      player_ids: &'a [&'a PlayerId],
+    }
+    let params = Params { player_ids };
+    let response = self.maybe_refresh(|client| {
+      client
+        .post(control_v1!(
+          "households/{}/groups/createGroup",
+          household.id
+        ))
+        .json(&params)
+    })?;
     let group_info: GroupInfo = response.json()?;
     Ok(group_info.group)
   }
@@ -9,6 +9,21 @@ use ronor::{Favorite, Group, Household, PlayModes, Player, Playlist, Sonos};
 use std::convert::TryFrom;
 use xdg::BaseDirectories;
 
+mod cache;
+mod cloud_queue;
+mod encoder;
+mod fade;
+mod file_server;
+mod gateway;
+mod interrupt;
+mod mpd;
+mod mpris;
+mod mqtt;
+mod now_playing;
+mod scene;
+mod schedule;
+mod ssml;
+
 error_chain! {
   errors {
     UnknownFavorite(name: String) {
@@ -43,7 +58,7 @@ error_chain! {
   }
 }
 
-trait CLI {
+trait Cli {
   fn run_subcmd(&mut self, name: &str, matches: &ArgMatches) -> Result<()>;
 }
 
@@ -55,7 +70,7 @@ macro_rules! subcmds {
     fn build_subcmds() -> Vec<Command> {
       vec![$($subcmds::$mod::build()),*]
     }
-    impl CLI for Sonos {
+    impl Cli for Sonos {
       fn run_subcmd(&mut self, name: &str, matches: &ArgMatches) -> Result<()> {
         match name {
           $($subcmds::$mod::NAME => $subcmds::$mod::run(self, matches),)*
@@ -68,10 +83,15 @@ macro_rules! subcmds {
 
 subcmds!(
   mod subcmds {
+    mod cache;
+    mod daemon;
+    mod fade;
     mod get_favorites;
     mod get_playlist;
     mod get_playlists;
     mod get_volume;
+    mod group;
+    mod home_theater;
     mod init;
     mod inventory;
     mod load_audio_clip;
@@ -81,15 +101,29 @@ subcmds!(
     mod load_playlist;
     mod login;
     mod modify_group;
+    mod move_playback;
+    mod mpd;
+    mod mpris;
+    mod mqtt;
     mod now_playing;
+    mod party;
     mod pause;
     mod play;
+    mod play_directory;
+    mod play_mode;
+    mod play_url;
+    mod scene;
+    mod schedule;
     mod seek;
+    mod serve;
     mod set_mute;
     mod set_volume;
     mod skip;
+    mod sleep;
     mod speak;
+    mod stop;
     mod toggle_play_pause;
+    mod up_next;
   }
 );
 
@@ -128,7 +162,7 @@ fn build() -> Command {
         .about("Generates completion scripts for your shell")
         .arg(
           Arg::new("SHELL")
-            .value_parser(PossibleValuesParser::new(&["bash", "fish", "zsh"]))
+            .value_parser(PossibleValuesParser::new(["bash", "fish", "zsh"]))
             .num_args(1)
             .required(true)
             .help("The shell to generate the script for"),
@@ -158,7 +192,7 @@ fn get_playback_status(sonos: &mut Sonos, matches: &ArgMatches) -> Result<()> {
     for group in sonos.get_groups(household)?.groups.iter().filter(|group| {
       matches
         .get_one::<String>("GROUP")
-        .map_or(true, |name| name == &group.name)
+        .is_none_or(|name| name == &group.name)
     }) {
       found = true;
       println!(
@@ -182,7 +216,7 @@ fn get_metadata_status(sonos: &mut Sonos, matches: &ArgMatches) -> Result<()> {
     for group in sonos.get_groups(household)?.groups.iter().filter(|group| {
       matches
         .get_one::<String>("GROUP")
-        .map_or(true, |name| name == &group.name)
+        .is_none_or(|name| name == &group.name)
     }) {
       found = true;
       println!(
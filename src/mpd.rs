@@ -0,0 +1,978 @@
+//! Speak enough of the MPD protocol for common clients to control a group.
+//!
+//! The group is the one a chosen player is part of, so it survives players
+//! joining and leaving.  The MPD queue always holds exactly what the group is
+//! playing, if anything.  Favorites and Sonos playlists are offered as stored
+//! playlists, `load` replaces whatever the group was playing.
+//!
+//! Every client gets a thread which parses commands, but they are executed
+//! one batch at a time by the thread which owns the connection to Sonos.
+//! That thread also polls for changes, which are reported to `idle` clients.
+
+use crate::{Error, ErrorKind, Result, ResultExt};
+use ronor::watch::{Change, Snapshot};
+use ronor::{Group, Household, PlaybackState, PlayerId, Sonos};
+use std::collections::BTreeSet;
+use std::fmt::Write as _;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const GREETING: &str = "OK MPD 0.23.5\n";
+
+/// The only song id ever used, MPD clients expect one.
+const SONG_ID: u32 = 1;
+
+const COMMANDS: [&str; 46] = [
+  "binarylimit", "clear", "close", "command_list_begin", "command_list_end",
+  "command_list_ok_begin", "commands", "consume", "currentsong", "decoders", "find",
+  "getvol", "idle", "list", "listall", "listallinfo", "listplaylist", "listplaylistinfo",
+  "listplaylists", "load", "lsinfo", "next", "noidle", "notcommands", "outputs", "pause",
+  "ping", "play", "playid", "playlistid", "playlistinfo", "plchanges", "plchangesposid",
+  "previous", "random", "repeat", "search", "seek", "seekcur", "seekid", "setvol", "single",
+  "stats", "status", "stop", "tagtypes"
+];
+
+/// Clients sending longer lines are disconnected.
+const MAX_LINE: u64 = 64 * 1024;
+
+const SUBSYSTEMS: [&str; 6] = ["mixer", "options", "output", "player", "playlist", "stored_playlist"];
+
+/// Error codes of the protocol.
+const ACK_ERROR_ARG: u8 = 2;
+const ACK_ERROR_UNKNOWN: u8 = 5;
+const ACK_ERROR_NO_EXIST: u8 = 50;
+const ACK_ERROR_SYSTEM: u8 = 52;
+
+struct Ack {
+  code: u8,
+  message: String
+}
+
+impl Ack {
+  fn argument(message: &str) -> Self {
+    Ack {
+      code: ACK_ERROR_ARG,
+      message: message.to_string()
+    }
+  }
+}
+
+impl From<Error> for Ack {
+  fn from(e: Error) -> Self {
+    let code = match e.kind() {
+      ErrorKind::UnknownFavorite(_) | ErrorKind::UnknownPlaylist(_) => ACK_ERROR_NO_EXIST,
+      _ => ACK_ERROR_SYSTEM
+    };
+    Ack {
+      code,
+      message: e.to_string()
+    }
+  }
+}
+
+impl From<ronor::Error> for Ack {
+  fn from(e: ronor::Error) -> Self {
+    Error::from(e).into()
+  }
+}
+
+enum Request {
+  Subscribe(usize, Sender<Event>),
+  /// Sent by a client once it is done, so it no longer gets events.
+  Unsubscribe(usize),
+  Run {
+    commands: Vec<Vec<String>>,
+    list_ok: bool,
+    reply: Sender<String>
+  }
+}
+
+enum Event {
+  Line(String),
+  Closed,
+  Changed(Vec<&'static str>)
+}
+
+/// What the thread owning the connection to Sonos knows.
+struct State<'a> {
+  household: &'a Household,
+  player: PlayerId,
+  snapshot: Snapshot,
+  taken: Instant,
+  /// Bumped whenever the track changes, clients use it to notice.
+  version: u32,
+  started: Instant
+}
+
+impl State<'_> {
+  fn group(&self) -> std::result::Result<&Group, Ack> {
+    self.snapshot.household.group_of(&self.player).ok_or_else(|| Ack {
+      code: ACK_ERROR_SYSTEM,
+      message: String::from("The player has left the household")
+    })
+  }
+
+  /// Replace the snapshot, and tell which subsystems changed.
+  fn refresh(&mut self, sonos: &mut Sonos) -> Result<Vec<&'static str>> {
+    let snapshot = Snapshot::take(sonos, self.household, false)?;
+    let old = self.snapshot.household.group_of(&self.player);
+    let new = snapshot.household.group_of(&self.player);
+    let mut changed = BTreeSet::new();
+    match (old, new) {
+      (Some(old), Some(new)) if old.coordinator_id == new.coordinator_id => {
+        for change in self.snapshot.diff(&snapshot) {
+          match change {
+            Change::TrackChanged { group, .. } if group == new.id => {
+              changed.insert("player");
+              changed.insert("playlist");
+            }
+            Change::PlaybackStateChanged { group, .. } if group == new.id => {
+              changed.insert("player");
+            }
+            Change::VolumeChanged { group, player: None, .. } if group == new.id => {
+              changed.insert("mixer");
+            }
+            Change::PlayerJoined { group, .. } | Change::PlayerLeft { group, .. }
+              if group == new.id =>
+            {
+              changed.insert("output");
+            }
+            Change::FavoritesVersionChanged { .. } => {
+              changed.insert("stored_playlist");
+            }
+            _ => {}
+          }
+        }
+        let modes = |snapshot: &Snapshot, group: &Group| {
+          snapshot.playback.get(&group.id).map(|playback| {
+            let modes = &playback.play_modes;
+            (modes.repeat, modes.repeat_one, modes.shuffle)
+          })
+        };
+        if modes(&self.snapshot, old) != modes(&snapshot, new) {
+          changed.insert("options");
+        }
+      }
+      _ => changed.extend(["mixer", "options", "output", "player", "playlist"])
+    }
+    if changed.contains("playlist") {
+      self.version += 1;
+    }
+    self.snapshot = snapshot;
+    self.taken = Instant::now();
+    Ok(changed.into_iter().collect())
+  }
+}
+
+/// Answer MPD clients on `address` (`HOST:PORT`) forever.
+///
+/// Commands control the group `player` is part of.  Once the first snapshot
+/// was taken, failures to poll are reported and retried.
+pub fn serve(
+  sonos: &mut Sonos,
+  household: &Household,
+  player: &str,
+  address: &str,
+  interval: Duration
+) -> Result<()> {
+  let snapshot = Snapshot::take(sonos, household, false)?;
+  let player = snapshot
+    .household
+    .player_by_name(player)
+    .ok_or_else(|| ErrorKind::UnknownPlayer(player.to_string()))?
+    .id
+    .clone();
+  let mut state = State {
+    household,
+    player,
+    snapshot,
+    taken: Instant::now(),
+    version: 1,
+    started: Instant::now()
+  };
+  let listener =
+    TcpListener::bind(address).chain_err(|| format!("Failed to listen on {}", address))?;
+  println!("Listening on {}", address);
+  let (requests, receiver) = channel();
+  thread::spawn(move || {
+    for (id, stream) in listener.incoming().flatten().enumerate() {
+      let requests = requests.clone();
+      thread::spawn(move || client(id, stream, requests));
+    }
+  });
+  let mut subscribers: Vec<(usize, Sender<Event>)> = Vec::new();
+  let mut deadline = Instant::now() + interval;
+  let mut stale = false;
+  loop {
+    let request = match receiver.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+      Ok(request) => Some(request),
+      Err(RecvTimeoutError::Timeout) => None,
+      Err(RecvTimeoutError::Disconnected) => return Err("Stopped accepting clients".into())
+    };
+    if stale || Instant::now() >= deadline {
+      match state.refresh(sonos) {
+        Ok(changed) if !changed.is_empty() => subscribers
+          .retain(|(_, subscriber)| subscriber.send(Event::Changed(changed.clone())).is_ok()),
+        Ok(_) => {}
+        Err(e) => eprintln!("{}", e)
+      }
+      stale = false;
+      deadline = Instant::now() + interval;
+    }
+    match request {
+      Some(Request::Subscribe(id, subscriber)) => subscribers.push((id, subscriber)),
+      Some(Request::Unsubscribe(id)) => subscribers.retain(|(other, _)| *other != id),
+      Some(Request::Run {
+        commands,
+        list_ok,
+        reply
+      }) => {
+        let response = respond(&commands, list_ok, |arguments| {
+          stale |= changes_state(&arguments[0]);
+          execute(sonos, &state, arguments)
+        });
+        let _ = reply.send(response);
+      }
+      None => {}
+    }
+  }
+}
+
+/// The response to a list of `commands`, which stops at the first error.
+///
+/// With `list_ok`, every successful command is followed by `list_OK`.
+fn respond<F>(commands: &[Vec<String>], list_ok: bool, mut execute: F) -> String
+where
+  F: FnMut(&[String]) -> std::result::Result<String, Ack>
+{
+  let mut response = String::new();
+  for (index, arguments) in commands.iter().enumerate() {
+    match execute(arguments) {
+      Ok(output) => {
+        response.push_str(&output);
+        if list_ok {
+          response.push_str("list_OK\n");
+        }
+      }
+      Err(ack) => {
+        response.push_str(&format_ack(ack.code, index, &arguments[0], &ack.message));
+        return response;
+      }
+    }
+  }
+  response.push_str("OK\n");
+  response
+}
+
+/// Talk to one client until it goes away.
+fn client(id: usize, stream: TcpStream, requests: Sender<Request>) {
+  let (events, receiver) = channel();
+  let mut reader = match stream.try_clone() {
+    Ok(reader) => BufReader::new(reader),
+    Err(_) => return
+  };
+  let lines = events.clone();
+  thread::spawn(move || {
+    while let Some(line) = read_line(&mut reader) {
+      if lines.send(Event::Line(line)).is_err() {
+        break;
+      }
+    }
+    let _ = lines.send(Event::Closed);
+  });
+  let mut writer = stream;
+  if writer.write_all(GREETING.as_bytes()).is_err()
+    || requests.send(Request::Subscribe(id, events)).is_err()
+  {
+    return;
+  }
+  let mut pending: BTreeSet<&'static str> = BTreeSet::new();
+  let mut idle: Option<Vec<String>> = None;
+  let mut list: Option<(bool, Vec<Vec<String>>)> = None;
+  while let Ok(event) = receiver.recv() {
+    let response = match event {
+      Event::Closed => break,
+      Event::Changed(subsystems) => {
+        pending.extend(subsystems);
+        match &idle {
+          Some(filter) => match wake(&mut pending, filter) {
+            Some(response) => {
+              idle = None;
+              response
+            }
+            None => continue
+          },
+          None => continue
+        }
+      }
+      Event::Line(line) if idle.is_some() => {
+        // Anything but noidle ends the connection of an idle client.
+        if line.trim() != "noidle" {
+          break;
+        }
+        idle = None;
+        String::from("OK\n")
+      }
+      Event::Line(line) => {
+        let arguments = match arguments(&line) {
+          Ok(arguments) if arguments.is_empty() => continue,
+          Ok(arguments) => arguments,
+          Err(message) => {
+            if writer.write_all(format_ack(ACK_ERROR_ARG, 0, "", message).as_bytes()).is_err() {
+              break;
+            }
+            continue;
+          }
+        };
+        match (&mut list, arguments[0].as_str()) {
+          (None, "command_list_begin") => {
+            list = Some((false, Vec::new()));
+            continue;
+          }
+          (None, "command_list_ok_begin") => {
+            list = Some((true, Vec::new()));
+            continue;
+          }
+          (Some(_), "command_list_end") => {
+            let (list_ok, commands) = list.take().unwrap();
+            match run(&requests, commands, list_ok) {
+              Some(response) => response,
+              None => break
+            }
+          }
+          (Some((_, commands)), _) => {
+            commands.push(arguments);
+            continue;
+          }
+          (None, "close") => break,
+          (None, "idle") => {
+            let filter = arguments[1..].to_vec();
+            if let Some(unknown) = filter.iter().find(|name| !SUBSYSTEMS.contains(&name.as_str())) {
+              format_ack(ACK_ERROR_ARG, 0, "idle", &format!("Unrecognized idle event: {}", unknown))
+            } else {
+              match wake(&mut pending, &filter) {
+                Some(response) => response,
+                None => {
+                  idle = Some(filter);
+                  continue;
+                }
+              }
+            }
+          }
+          (None, "noidle") => String::from("OK\n"),
+          (None, "ping") => String::from("OK\n"),
+          (None, _) => match run(&requests, vec![arguments], false) {
+            Some(response) => response,
+            None => break
+          }
+        }
+      }
+    };
+    if writer.write_all(response.as_bytes()).is_err() {
+      break;
+    }
+  }
+  let _ = requests.send(Request::Unsubscribe(id));
+  // The reading thread holds on to the socket, so closing it takes more than dropping ours.
+  let _ = writer.shutdown(Shutdown::Both);
+}
+
+/// The next line from `reader`, `None` once it is closed or a line exceeds
+/// `MAX_LINE` bytes.
+fn read_line<R: BufRead>(reader: &mut R) -> Option<String> {
+  let mut line = Vec::new();
+  match reader.take(MAX_LINE + 1).read_until(b'\n', &mut line) {
+    Ok(0) | Err(_) => return None,
+    Ok(_) => {}
+  }
+  if line.ends_with(b"\n") {
+    line.pop();
+    if line.ends_with(b"\r") {
+      line.pop();
+    }
+  } else if line.len() as u64 > MAX_LINE {
+    return None;
+  }
+  String::from_utf8(line).ok()
+}
+
+/// Have `commands` executed by the thread owning the connection to Sonos.
+fn run(requests: &Sender<Request>, commands: Vec<Vec<String>>, list_ok: bool) -> Option<String> {
+  let (reply, response) = channel();
+  requests
+    .send(Request::Run {
+      commands,
+      list_ok,
+      reply
+    })
+    .ok()?;
+  response.recv().ok()
+}
+
+/// The response to `idle`, if any of the subsystems in `filter` changed.
+fn wake(pending: &mut BTreeSet<&'static str>, filter: &[String]) -> Option<String> {
+  let woken: Vec<&'static str> = pending
+    .iter()
+    .copied()
+    .filter(|subsystem| filter.is_empty() || filter.iter().any(|name| name == subsystem))
+    .collect();
+  if woken.is_empty() {
+    return None;
+  }
+  let mut response = String::new();
+  for subsystem in woken {
+    pending.remove(subsystem);
+    let _ = writeln!(response, "changed: {}", subsystem);
+  }
+  response.push_str("OK\n");
+  Some(response)
+}
+
+/// Whether `command` might change what the next `status` reports.
+fn changes_state(command: &str) -> bool {
+  matches!(
+    command,
+    "load"
+      | "next"
+      | "pause"
+      | "play"
+      | "playid"
+      | "previous"
+      | "random"
+      | "repeat"
+      | "seek"
+      | "seekcur"
+      | "seekid"
+      | "setvol"
+      | "single"
+      | "stop"
+      | "volume"
+  )
+}
+
+fn execute(sonos: &mut Sonos, state: &State, arguments: &[String]) -> std::result::Result<String, Ack> {
+  let command = arguments[0].as_str();
+  let arguments = &arguments[1..];
+  let mut output = String::new();
+  match command {
+    "status" => status(state, &mut output)?,
+    "currentsong" | "playlistinfo" | "playlistid" | "plchanges" => {
+      current_song(state, &mut output)?
+    }
+    "plchangesposid" => {
+      if queue_length(state)? > 0 {
+        let _ = write!(output, "cpos: 0\nId: {}\n", SONG_ID);
+      }
+    }
+    "play" | "playid" => sonos.play(state.group()?)?,
+    "pause" => {
+      let group = state.group()?;
+      let pause = match arguments.first().map(String::as_str) {
+        Some(flag) => boolean(flag)?,
+        None => matches!(group.playback_state, PlaybackState::Playing | PlaybackState::Buffering)
+      };
+      if pause {
+        sonos.pause_or_stop(group)?;
+      } else {
+        sonos.play(group)?;
+      }
+    }
+    "stop" => {
+      let group = state.group()?;
+      if group.playback_state != PlaybackState::Idle {
+        sonos.stop(group)?;
+      }
+    }
+    "next" => sonos.skip_to_next_track(state.group()?)?,
+    "previous" => sonos.skip_to_previous_track(state.group()?)?,
+    "seekcur" | "seek" | "seekid" => {
+      let time = match (command, arguments) {
+        ("seekcur", [time]) | ("seek", [_, time]) | ("seekid", [_, time]) => time.as_str(),
+        _ => return Err(Ack::argument("Wrong number of arguments"))
+      };
+      let seconds: f64 = time.parse().map_err(|_| Ack::argument("Invalid time"))?;
+      let millis = (seconds * 1000.0).round();
+      let group = state.group()?;
+      if command == "seekcur" && (time.starts_with('+') || time.starts_with('-')) {
+        sonos.seek_relative(group, millis as i128, None)?;
+      } else if millis >= 0.0 {
+        sonos.seek(group, millis as u128, None)?;
+      } else {
+        return Err(Ack::argument("Invalid time"));
+      }
+    }
+    "setvol" => {
+      let volume: u8 = single(arguments)?
+        .parse()
+        .ok()
+        .filter(|volume| *volume <= 100)
+        .ok_or_else(|| Ack::argument("Invalid volume value"))?;
+      sonos.set_group_volume(state.group()?, volume)?;
+    }
+    "volume" => {
+      let delta: i8 = single(arguments)?
+        .parse()
+        .ok()
+        .filter(|delta: &i8| (-100..=100).contains(delta))
+        .ok_or_else(|| Ack::argument("Invalid volume value"))?;
+      sonos.set_relative_group_volume(state.group()?, delta)?;
+    }
+    "getvol" => {
+      let volume = state.snapshot.group_volumes.get(&state.group()?.id);
+      if let Some(volume) = volume {
+        let _ = writeln!(output, "volume: {}", volume.volume);
+      }
+    }
+    "repeat" | "random" | "single" => {
+      let flag = boolean(single(arguments)?)?;
+      let group = state.group()?;
+      let playback = state
+        .snapshot
+        .playback
+        .get(&group.id)
+        .ok_or_else(|| Ack::argument("Playback status is not known yet"))?;
+      let mut play_modes = playback.play_modes.clone();
+      match command {
+        "repeat" => play_modes.repeat = flag,
+        "random" => play_modes.shuffle = flag,
+        _ => play_modes.repeat_one = flag
+      }
+      sonos.set_play_modes(group, &play_modes)?;
+    }
+    "consume" => {
+      if boolean(single(arguments)?)? {
+        return Err(Ack::argument("Consume mode is not supported"));
+      }
+    }
+    "listplaylists" => {
+      for name in stored_playlists(sonos, state)? {
+        let _ = writeln!(output, "playlist: {}", single_line(&name));
+      }
+    }
+    "lsinfo" => {
+      if arguments.first().is_none_or(|uri| uri.is_empty() || uri == "/") {
+        for name in stored_playlists(sonos, state)? {
+          let _ = writeln!(output, "playlist: {}", single_line(&name));
+        }
+      }
+    }
+    "listplaylist" | "listplaylistinfo" => {
+      let name = single(arguments)?;
+      let favorites = sonos.get_favorites(state.household)?;
+      if let Some(favorite) = favorites.items.iter().find(|favorite| &favorite.name == name) {
+        let _ = writeln!(output, "file: sonos:favorite:{}", favorite.id);
+        if command == "listplaylistinfo" {
+          let _ = writeln!(output, "Title: {}", single_line(&favorite.name));
+          if let Some(description) = &favorite.description {
+            let _ = writeln!(output, "Name: {}", single_line(description));
+          }
+        }
+      } else {
+        let playlist = sonos
+          .get_playlists(state.household)?
+          .playlists
+          .into_iter()
+          .find(|playlist| &playlist.name == name)
+          .ok_or_else(|| ErrorKind::UnknownPlaylist(name.to_string()))
+          .map_err(Error::from)?;
+        let summary = sonos.get_playlist(state.household, &playlist)?;
+        for (index, track) in summary.tracks.iter().enumerate() {
+          let _ = writeln!(output, "file: sonos:playlist:{}:{}", playlist.id, index);
+          if command == "listplaylistinfo" {
+            let _ = writeln!(output, "Title: {}", single_line(&track.name));
+            let _ = writeln!(output, "Artist: {}", single_line(&track.artist));
+            if let Some(album) = &track.album {
+              let _ = writeln!(output, "Album: {}", single_line(album));
+            }
+          }
+        }
+      }
+    }
+    "load" => {
+      let name = arguments.first().ok_or_else(|| Ack::argument("Missing playlist name"))?;
+      let group = state.group()?;
+      let favorites = sonos.get_favorites(state.household)?;
+      if let Some(favorite) = favorites.items.iter().find(|favorite| &favorite.name == name) {
+        sonos.load_favorite(group, favorite, false, None)?;
+      } else {
+        let playlist = sonos
+          .get_playlists(state.household)?
+          .playlists
+          .into_iter()
+          .find(|playlist| &playlist.name == name)
+          .ok_or_else(|| ErrorKind::UnknownPlaylist(name.to_string()))
+          .map_err(Error::from)?;
+        sonos.load_playlist(group, &playlist, false, None)?;
+      }
+    }
+    // Loading replaces what the group plays anyway, so there is nothing to clear.
+    "clear" => {}
+    "outputs" => {
+      let group = state.group()?;
+      for (index, player) in state.snapshot.household.members(group).enumerate() {
+        let _ = write!(
+          output,
+          "outputid: {}\noutputname: {}\nplugin: sonos\noutputenabled: 1\n",
+          index,
+          single_line(&player.name)
+        );
+      }
+    }
+    "stats" => {
+      let _ = write!(
+        output,
+        "uptime: {}\nplaytime: 0\nartists: 0\nalbums: 0\nsongs: 0\ndb_playtime: 0\ndb_update: 0\n",
+        state.started.elapsed().as_secs()
+      );
+    }
+    "tagtypes" => {
+      if arguments.is_empty() {
+        output.push_str("tagtype: Artist\ntagtype: Album\ntagtype: Title\ntagtype: Name\n");
+      }
+    }
+    "commands" => {
+      for command in COMMANDS.iter() {
+        let _ = writeln!(output, "command: {}", command);
+      }
+    }
+    "notcommands" | "decoders" | "binarylimit" => {}
+    // There is no music database to search.
+    "list" | "find" | "search" | "listall" | "listallinfo" => {}
+    _ => {
+      return Err(Ack {
+        code: ACK_ERROR_UNKNOWN,
+        message: format!("unknown command \"{}\"", command)
+      })
+    }
+  }
+  Ok(output)
+}
+
+fn status(state: &State, output: &mut String) -> std::result::Result<(), Ack> {
+  let group = state.group()?;
+  if let Some(volume) = state.snapshot.group_volumes.get(&group.id) {
+    let _ = writeln!(output, "volume: {}", volume.volume);
+  }
+  if let Some(playback) = state.snapshot.playback.get(&group.id) {
+    let modes = &playback.play_modes;
+    let _ = write!(
+      output,
+      "repeat: {}\nrandom: {}\nsingle: {}\nconsume: 0\n",
+      u8::from(modes.repeat || modes.repeat_one),
+      u8::from(modes.shuffle),
+      u8::from(modes.repeat_one)
+    );
+  }
+  let length = queue_length(state)?;
+  let _ = write!(
+    output,
+    "playlist: {}\nplaylistlength: {}\nstate: {}\n",
+    state.version,
+    length,
+    match group.playback_state {
+      PlaybackState::Playing | PlaybackState::Buffering => "play",
+      PlaybackState::Paused => "pause",
+      PlaybackState::Idle => "stop"
+    }
+  );
+  if length > 0 {
+    let _ = write!(output, "song: 0\nsongid: {}\n", SONG_ID);
+    let elapsed = elapsed_millis(state, group) as f64 / 1000.0;
+    match duration_millis(state, group) {
+      Some(duration) => {
+        let duration = duration as f64 / 1000.0;
+        let _ = write!(
+          output,
+          "time: {:.0}:{:.0}\nelapsed: {:.3}\nduration: {:.3}\n",
+          elapsed.floor(),
+          duration.floor(),
+          elapsed,
+          duration
+        );
+      }
+      None => {
+        let _ = writeln!(output, "elapsed: {:.3}", elapsed);
+      }
+    }
+  }
+  Ok(())
+}
+
+/// Describe what the group plays as the only song in the queue.
+fn current_song(state: &State, output: &mut String) -> std::result::Result<(), Ack> {
+  let group = state.group()?;
+  let metadata = match state.snapshot.metadata.get(&group.id) {
+    Some(metadata) if queue_length(state)? > 0 => metadata,
+    _ => return Ok(())
+  };
+  let track = metadata.current_item.as_ref().map(|item| &item.track);
+  let file = track
+    .and_then(|track| track.id.as_ref())
+    .or_else(|| metadata.container.as_ref().and_then(|container| container.id.as_ref()))
+    .map(|id| format!("sonos:{}", id.object_id))
+    .unwrap_or_else(|| String::from("sonos:stream"));
+  let _ = writeln!(output, "file: {}", single_line(&file));
+  if let Some(track) = track {
+    if let Some(name) = &track.name {
+      let _ = writeln!(output, "Title: {}", single_line(name));
+    }
+    if let Some(artist) = &track.artist {
+      let _ = writeln!(output, "Artist: {}", single_line(&artist.name));
+    }
+    if let Some(album) = &track.album {
+      let _ = writeln!(output, "Album: {}", single_line(&album.name));
+    }
+  }
+  let name = metadata
+    .stream_info
+    .as_ref()
+    .or_else(|| metadata.container.as_ref().and_then(|container| container.name.as_ref()));
+  if let Some(name) = name {
+    let _ = writeln!(output, "Name: {}", single_line(name));
+  }
+  if let Some(duration) = duration_millis(state, group) {
+    let _ = write!(
+      output,
+      "Time: {}\nduration: {:.3}\n",
+      duration / 1000,
+      duration as f64 / 1000.0
+    );
+  }
+  let _ = write!(output, "Pos: 0\nId: {}\n", SONG_ID);
+  Ok(())
+}
+
+/// One if the group has something to play, zero otherwise.
+fn queue_length(state: &State) -> std::result::Result<u8, Ack> {
+  let group = state.group()?;
+  Ok(match state.snapshot.metadata.get(&group.id) {
+    Some(metadata)
+      if metadata.current_item.is_some()
+        || metadata.container.is_some()
+        || metadata.stream_info.is_some() =>
+    {
+      1
+    }
+    _ => 0
+  })
+}
+
+fn duration_millis(state: &State, group: &Group) -> Option<i64> {
+  state
+    .snapshot
+    .metadata
+    .get(&group.id)?
+    .current_item
+    .as_ref()?
+    .track
+    .duration_millis
+    .filter(|duration| *duration > 0)
+    .map(i64::from)
+}
+
+/// The position at the time of the snapshot, advanced if playing.
+fn elapsed_millis(state: &State, group: &Group) -> i64 {
+  let position = state
+    .snapshot
+    .playback
+    .get(&group.id)
+    .map(|playback| playback.position_millis.max(0))
+    .unwrap_or(0);
+  let position = if group.playback_state == PlaybackState::Playing {
+    position + state.taken.elapsed().as_millis() as i64
+  } else {
+    position
+  };
+  match duration_millis(state, group) {
+    Some(duration) => position.min(duration),
+    None => position
+  }
+}
+
+/// Favorites first, then Sonos playlists.
+fn stored_playlists(sonos: &mut Sonos, state: &State) -> Result<Vec<String>> {
+  let mut names: Vec<String> = sonos
+    .get_favorites(state.household)?
+    .items
+    .into_iter()
+    .map(|favorite| favorite.name)
+    .collect();
+  names.extend(
+    sonos
+      .get_playlists(state.household)?
+      .playlists
+      .into_iter()
+      .map(|playlist| playlist.name)
+  );
+  Ok(names)
+}
+
+fn single(arguments: &[String]) -> std::result::Result<&String, Ack> {
+  match arguments {
+    [argument] => Ok(argument),
+    _ => Err(Ack::argument("Wrong number of arguments"))
+  }
+}
+
+fn boolean(argument: &str) -> std::result::Result<bool, Ack> {
+  match argument {
+    "0" => Ok(false),
+    "1" => Ok(true),
+    _ => Err(Ack::argument("Boolean (0/1) expected"))
+  }
+}
+
+/// An error response, `index` is the position of `command` in a command list.
+fn format_ack(code: u8, index: usize, command: &str, message: &str) -> String {
+  format!("ACK [{}@{}] {{{}}} {}\n", code, index, command, single_line(message))
+}
+
+/// Values are terminated by newlines, so they must not contain any.
+fn single_line(value: &str) -> String {
+  value.replace(['\r', '\n'], " ")
+}
+
+/// Split a request line into words, honouring double quotes and backslashes.
+fn arguments(line: &str) -> std::result::Result<Vec<String>, &'static str> {
+  let mut arguments = Vec::new();
+  let mut chars = line.trim().chars().peekable();
+  while let Some(&c) = chars.peek() {
+    if c.is_whitespace() {
+      chars.next();
+      continue;
+    }
+    let mut argument = String::new();
+    if c == '"' {
+      chars.next();
+      loop {
+        match chars.next() {
+          Some('"') => break,
+          Some('\\') => argument.push(chars.next().ok_or("Missing closing '\"'")?),
+          Some(c) => argument.push(c),
+          None => return Err("Missing closing '\"'")
+        }
+      }
+    } else {
+      while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+          break;
+        }
+        argument.push(c);
+        chars.next();
+      }
+    }
+    arguments.push(argument);
+  }
+  Ok(arguments)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn words(words: &[&str]) -> Vec<String> {
+    words.iter().map(|word| word.to_string()).collect()
+  }
+
+  #[test]
+  fn splits_arguments() {
+    assert_eq!(arguments("  play  3 ").unwrap(), words(&["play", "3"]));
+    assert_eq!(arguments("").unwrap(), Vec::<String>::new());
+  }
+
+  #[test]
+  fn quoted_arguments() {
+    assert_eq!(
+      arguments(r#"load "Dub Reflections" x"#).unwrap(),
+      words(&["load", "Dub Reflections", "x"])
+    );
+    assert_eq!(arguments(r#"find "" any"#).unwrap(), words(&["find", "", "any"]));
+  }
+
+  #[test]
+  fn escaped_arguments() {
+    assert_eq!(
+      arguments(r#"load "say \"hi\" C:\\Music""#).unwrap(),
+      words(&["load", r#"say "hi" C:\Music"#])
+    );
+  }
+
+  #[test]
+  fn unterminated_quotes() {
+    assert!(arguments(r#"load "Dub"#).is_err());
+    assert!(arguments(r#"load "Dub\"#).is_err());
+  }
+
+  #[test]
+  fn reads_lines() {
+    let mut reader = std::io::Cursor::new("status\r\nplay 1\nping");
+    assert_eq!(read_line(&mut reader).as_deref(), Some("status"));
+    assert_eq!(read_line(&mut reader).as_deref(), Some("play 1"));
+    assert_eq!(read_line(&mut reader).as_deref(), Some("ping"));
+    assert_eq!(read_line(&mut reader), None);
+  }
+
+  #[test]
+  fn drops_overlong_lines() {
+    let fits = format!("{}\n", "x".repeat(MAX_LINE as usize));
+    assert!(read_line(&mut std::io::Cursor::new(fits)).is_some());
+    let endless = "x".repeat(MAX_LINE as usize + 1);
+    assert_eq!(read_line(&mut std::io::Cursor::new(endless)), None);
+  }
+
+  #[test]
+  fn acks_name_the_failing_command() {
+    let commands = vec![words(&["ping"]), words(&["status"]), words(&["bogus"]), words(&["stop"])];
+    let mut executed = Vec::new();
+    let response = respond(&commands, true, |arguments| {
+      executed.push(arguments[0].clone());
+      match arguments[0].as_str() {
+        "bogus" => Err(Ack {
+          code: ACK_ERROR_UNKNOWN,
+          message: String::from("unknown command \"bogus\"")
+        }),
+        _ => Ok(String::new())
+      }
+    });
+    assert_eq!(
+      response,
+      "list_OK\nlist_OK\nACK [5@2] {bogus} unknown command \"bogus\"\n"
+    );
+    assert_eq!(executed, words(&["ping", "status", "bogus"]));
+  }
+
+  #[test]
+  fn command_lists_end_with_ok() {
+    let commands = vec![words(&["ping"]), words(&["ping"])];
+    assert_eq!(respond(&commands, true, |_| Ok(String::new())), "list_OK\nlist_OK\nOK\n");
+    assert_eq!(respond(&commands, false, |_| Ok(String::new())), "OK\n");
+  }
+
+  #[test]
+  fn acks_are_single_lines() {
+    assert_eq!(format_ack(2, 0, "", "bad\r\nline"), "ACK [2@0] {} bad  line\n");
+  }
+
+  #[test]
+  fn idle_waits_for_changes() {
+    let mut pending = BTreeSet::new();
+    assert_eq!(wake(&mut pending, &[]), None);
+    pending.extend(["player", "mixer"]);
+    assert_eq!(wake(&mut pending, &[]).unwrap(), "changed: mixer\nchanged: player\nOK\n");
+    assert!(pending.is_empty());
+  }
+
+  #[test]
+  fn idle_filters_subsystems() {
+    let mut pending = BTreeSet::from(["mixer", "options", "player"]);
+    assert_eq!(wake(&mut pending, &words(&["playlist"])), None);
+    assert_eq!(
+      wake(&mut pending, &words(&["player", "options"])).unwrap(),
+      "changed: options\nchanged: player\nOK\n"
+    );
+    assert_eq!(pending, BTreeSet::from(["mixer"]));
+  }
+}
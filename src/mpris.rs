@@ -0,0 +1,584 @@
+//! Expose every group of a household as an MPRIS media player.
+//!
+//! Each group gets its own bus name on the session bus, derived from the
+//! name of its coordinator, and serves `org.mpris.MediaPlayer2` and
+//! `org.mpris.MediaPlayer2.Player` at `/org/mpris/MediaPlayer2`.
+//! Properties are refreshed by polling.  Method calls are queued and
+//! executed by the polling thread, which owns the connection to Sonos.
+
+use crate::{Result, ResultExt};
+use ronor::watch::Snapshot;
+use ronor::{Group, Household, HouseholdSnapshot, PlayModes, PlaybackState, PlayerId, Sonos};
+use std::collections::HashMap;
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::time::{Duration, Instant};
+use zbus::blocking::connection::Builder;
+use zbus::blocking::Connection;
+use zbus::object_server::SignalEmitter;
+use zbus::zvariant::{ObjectPath, Value};
+use zbus::{block_on, fdo, interface};
+
+const PATH: &str = "/org/mpris/MediaPlayer2";
+const NO_TRACK: &str = "/org/mpris/MediaPlayer2/TrackList/NoTrack";
+
+/// Position jumps larger than this are announced as `Seeked`.
+const SEEK_THRESHOLD_MILLIS: i64 = 3000;
+
+/// A group published on the session bus.
+struct Bridge {
+  name: String,
+  connection: Connection
+}
+
+enum Command {
+  Play,
+  Pause,
+  PlayPause,
+  Stop,
+  Next,
+  Previous,
+  Seek(i64),
+  SetPosition(i64),
+  Volume(f64),
+  PlayModes(PlayModes)
+}
+
+/// What a group looks like to MPRIS clients, apart from the position.
+#[derive(Clone, PartialEq)]
+struct Status {
+  identity: String,
+  playback_status: &'static str,
+  item_id: Option<String>,
+  title: Option<String>,
+  artist: Option<String>,
+  album: Option<String>,
+  art_url: Option<String>,
+  length_millis: Option<i64>,
+  volume: u8,
+  play_modes: (bool, bool, bool, bool),
+  can_go_next: bool,
+  can_go_previous: bool,
+  can_play: bool,
+  can_pause: bool,
+  can_seek: bool
+}
+
+impl Status {
+  fn new(snapshot: &Snapshot, group: &Group) -> Option<(Self, i64)> {
+    let playback = snapshot.playback.get(&group.id)?;
+    let metadata = snapshot.metadata.get(&group.id)?;
+    let volume = snapshot.group_volumes.get(&group.id)?;
+    let item = metadata.current_item.as_ref();
+    let track = item.map(|item| &item.track);
+    let actions = &playback.available_playback_actions;
+    let status = Status {
+      identity: group.name.clone(),
+      playback_status: match playback.playback_state {
+        PlaybackState::Playing | PlaybackState::Buffering => "Playing",
+        PlaybackState::Paused => "Paused",
+        PlaybackState::Idle => "Stopped"
+      },
+      item_id: item.and_then(|item| item.id.clone()),
+      title: track
+        .and_then(|track| track.name.clone())
+        .or_else(|| metadata.stream_info.clone()),
+      artist: track.and_then(|track| track.artist.as_ref().map(|artist| artist.name.clone())),
+      album: track
+        .and_then(|track| track.album.as_ref().map(|album| album.name.clone()))
+        .or_else(|| metadata.container.as_ref().and_then(|container| container.name.clone())),
+      art_url: track.and_then(|track| track.image_url.clone()),
+      length_millis: track
+        .and_then(|track| track.duration_millis)
+        .filter(|duration| *duration > 0)
+        .map(i64::from),
+      volume: volume.volume,
+      play_modes: (
+        playback.play_modes.repeat,
+        playback.play_modes.repeat_one,
+        playback.play_modes.crossfade,
+        playback.play_modes.shuffle
+      ),
+      can_go_next: actions.can_skip,
+      can_go_previous: actions.can_skip_back,
+      can_play: metadata.container.is_some() || item.is_some(),
+      can_pause: actions.can_pause || actions.can_stop,
+      can_seek: actions.can_seek
+    };
+    Some((status, playback.position_millis.max(0)))
+  }
+
+  fn track_id(&self) -> ObjectPath<'static> {
+    let path = match &self.item_id {
+      Some(id) => {
+        let hex: String = id.bytes().map(|byte| format!("{:02x}", byte)).collect();
+        format!("/guru/blind/ronor/track/_{}", hex)
+      }
+      None => String::from(NO_TRACK)
+    };
+    ObjectPath::try_from(path).unwrap()
+  }
+
+  fn play_modes(&self) -> PlayModes {
+    let (repeat, repeat_one, crossfade, shuffle) = self.play_modes;
+    PlayModes {
+      repeat,
+      repeat_one,
+      crossfade,
+      shuffle
+    }
+  }
+}
+
+struct Root {
+  identity: String
+}
+
+#[interface(name = "org.mpris.MediaPlayer2")]
+impl Root {
+  fn raise(&self) {}
+
+  fn quit(&self) {}
+
+  #[zbus(property)]
+  fn can_quit(&self) -> bool {
+    false
+  }
+
+  #[zbus(property)]
+  fn can_raise(&self) -> bool {
+    false
+  }
+
+  #[zbus(property)]
+  fn has_track_list(&self) -> bool {
+    false
+  }
+
+  #[zbus(property)]
+  fn identity(&self) -> String {
+    self.identity.clone()
+  }
+
+  #[zbus(property)]
+  fn supported_uri_schemes(&self) -> Vec<String> {
+    Vec::new()
+  }
+
+  #[zbus(property)]
+  fn supported_mime_types(&self) -> Vec<String> {
+    Vec::new()
+  }
+}
+
+struct Player {
+  coordinator: PlayerId,
+  commands: Sender<(PlayerId, Command)>,
+  status: Status,
+  position_millis: i64,
+  updated: Instant
+}
+
+impl Player {
+  fn send(&self, command: Command) {
+    // The receiver only goes away when the bridge shuts down.
+    let _ = self.commands.send((self.coordinator.clone(), command));
+  }
+
+  /// The position at this very moment, assuming playback went on.
+  fn current_position_millis(&self) -> i64 {
+    let mut position = self.position_millis;
+    if self.status.playback_status == "Playing" {
+      position += self.updated.elapsed().as_millis() as i64;
+    }
+    match self.status.length_millis {
+      Some(length) => position.min(length),
+      None => position
+    }
+  }
+}
+
+#[interface(name = "org.mpris.MediaPlayer2.Player")]
+impl Player {
+  fn next(&self) {
+    self.send(Command::Next)
+  }
+
+  fn previous(&self) {
+    self.send(Command::Previous)
+  }
+
+  fn pause(&self) {
+    self.send(Command::Pause)
+  }
+
+  fn play_pause(&self) {
+    self.send(Command::PlayPause)
+  }
+
+  fn stop(&self) {
+    self.send(Command::Stop)
+  }
+
+  fn play(&self) {
+    self.send(Command::Play)
+  }
+
+  fn seek(&self, offset: i64) {
+    if self.status.can_seek {
+      self.send(Command::Seek(offset))
+    }
+  }
+
+  fn set_position(&self, track_id: ObjectPath<'_>, position: i64) {
+    if self.status.can_seek && track_id == self.status.track_id() && position >= 0 {
+      self.send(Command::SetPosition(position))
+    }
+  }
+
+  fn open_uri(&self, _uri: &str) -> fdo::Result<()> {
+    Err(fdo::Error::NotSupported(String::from("Opening URIs is not supported")))
+  }
+
+  #[zbus(signal)]
+  async fn seeked(emitter: &SignalEmitter<'_>, position: i64) -> zbus::Result<()>;
+
+  #[zbus(property)]
+  fn playback_status(&self) -> String {
+    String::from(self.status.playback_status)
+  }
+
+  #[zbus(property)]
+  fn loop_status(&self) -> String {
+    String::from(match self.status.play_modes {
+      (_, true, _, _) => "Track",
+      (true, false, _, _) => "Playlist",
+      _ => "None"
+    })
+  }
+
+  #[zbus(property)]
+  fn set_loop_status(&mut self, loop_status: String) {
+    let mut play_modes = self.status.play_modes();
+    play_modes.repeat = loop_status == "Playlist";
+    play_modes.repeat_one = loop_status == "Track";
+    self.send(Command::PlayModes(play_modes))
+  }
+
+  #[zbus(property)]
+  fn shuffle(&self) -> bool {
+    self.status.play_modes.3
+  }
+
+  #[zbus(property)]
+  fn set_shuffle(&mut self, shuffle: bool) {
+    let mut play_modes = self.status.play_modes();
+    play_modes.shuffle = shuffle;
+    self.send(Command::PlayModes(play_modes))
+  }
+
+  #[zbus(property)]
+  fn rate(&self) -> f64 {
+    1.0
+  }
+
+  #[zbus(property)]
+  fn set_rate(&mut self, _rate: f64) {}
+
+  #[zbus(property)]
+  fn minimum_rate(&self) -> f64 {
+    1.0
+  }
+
+  #[zbus(property)]
+  fn maximum_rate(&self) -> f64 {
+    1.0
+  }
+
+  #[zbus(property)]
+  fn metadata(&self) -> HashMap<String, Value<'static>> {
+    let status = &self.status;
+    let mut metadata = HashMap::new();
+    metadata.insert(String::from("mpris:trackid"), Value::from(status.track_id()));
+    if let Some(title) = &status.title {
+      metadata.insert(String::from("xesam:title"), Value::from(title.clone()));
+    }
+    if let Some(artist) = &status.artist {
+      metadata.insert(String::from("xesam:artist"), Value::from(vec![artist.clone()]));
+    }
+    if let Some(album) = &status.album {
+      metadata.insert(String::from("xesam:album"), Value::from(album.clone()));
+    }
+    if let Some(art_url) = &status.art_url {
+      metadata.insert(String::from("mpris:artUrl"), Value::from(art_url.clone()));
+    }
+    if let Some(length) = status.length_millis {
+      metadata.insert(String::from("mpris:length"), Value::from(length * 1000));
+    }
+    metadata
+  }
+
+  #[zbus(property)]
+  fn volume(&self) -> f64 {
+    f64::from(self.status.volume) / 100.0
+  }
+
+  #[zbus(property)]
+  fn set_volume(&mut self, volume: f64) {
+    self.send(Command::Volume(volume))
+  }
+
+  #[zbus(property(emits_changed_signal = "false"))]
+  fn position(&self) -> i64 {
+    self.current_position_millis() * 1000
+  }
+
+  #[zbus(property)]
+  fn can_go_next(&self) -> bool {
+    self.status.can_go_next
+  }
+
+  #[zbus(property)]
+  fn can_go_previous(&self) -> bool {
+    self.status.can_go_previous
+  }
+
+  #[zbus(property)]
+  fn can_play(&self) -> bool {
+    self.status.can_play
+  }
+
+  #[zbus(property)]
+  fn can_pause(&self) -> bool {
+    self.status.can_pause
+  }
+
+  #[zbus(property)]
+  fn can_seek(&self) -> bool {
+    self.status.can_seek
+  }
+
+  #[zbus(property(emits_changed_signal = "const"))]
+  fn can_control(&self) -> bool {
+    true
+  }
+}
+
+/// Bridge all groups of `household` until something goes badly wrong.
+///
+/// Once the first snapshot was taken, failures are reported and retried.
+pub fn serve(sonos: &mut Sonos, household: &Household, interval: Duration) -> Result<()> {
+  let (commands, receiver) = channel();
+  let mut bridges: HashMap<PlayerId, Bridge> = HashMap::new();
+  let mut first = true;
+  loop {
+    match refresh(sonos, household, &mut bridges, &commands) {
+      Ok(()) => {}
+      Err(e) if !first => eprintln!("{}", e),
+      Err(e) => return Err(e)
+    }
+    first = false;
+    let deadline = Instant::now() + interval;
+    match receiver.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+      Ok((coordinator, command)) => {
+        if let Err(e) = execute(sonos, household, &coordinator, command) {
+          eprintln!("{}", e);
+        }
+      }
+      Err(RecvTimeoutError::Timeout) => {}
+      Err(RecvTimeoutError::Disconnected) => unreachable!()
+    }
+  }
+}
+
+/// Take a new snapshot and create, update and remove bridges to match it.
+fn refresh(
+  sonos: &mut Sonos,
+  household: &Household,
+  bridges: &mut HashMap<PlayerId, Bridge>,
+  commands: &Sender<(PlayerId, Command)>
+) -> Result<()> {
+  let snapshot = Snapshot::take(sonos, household, false)?;
+  bridges.retain(|coordinator, _| snapshot.household.coordinated_by(coordinator).is_some());
+  for group in snapshot.household.groups().iter() {
+    let (status, position_millis) = match Status::new(&snapshot, group) {
+      Some(status) => status,
+      None => continue
+    };
+    match bridges.get(&group.coordinator_id) {
+      Some(bridge) => update(&bridge.connection, status, position_millis)
+        .chain_err(|| format!("Failed to update {} on the session bus", group.name))?,
+      None => {
+        let coordinator = snapshot
+          .household
+          .coordinator(group)
+          .map(|player| player.name.as_str())
+          .unwrap_or(group.name.as_str());
+        let taken = bridges.values().map(|bridge| bridge.name.as_str());
+        let name = bus_name(coordinator, &group.coordinator_id, taken);
+        let player = Player {
+          coordinator: group.coordinator_id.clone(),
+          commands: commands.clone(),
+          status,
+          position_millis,
+          updated: Instant::now()
+        };
+        let connection = publish(&name, player)
+          .chain_err(|| format!("Failed to publish {} on the session bus", name))?;
+        bridges.insert(group.coordinator_id.clone(), Bridge { name, connection });
+      }
+    }
+  }
+  Ok(())
+}
+
+fn publish(name: &str, player: Player) -> zbus::Result<Connection> {
+  let root = Root {
+    identity: player.status.identity.clone()
+  };
+  Builder::session()?
+    .name(name)?
+    .serve_at(PATH, root)?
+    .serve_at(PATH, player)?
+    .build()
+}
+
+/// Replace the status of a bridge and tell clients what changed.
+fn update(connection: &Connection, status: Status, position_millis: i64) -> zbus::Result<()> {
+  let server = connection.object_server();
+  let player = server.interface::<_, Player>(PATH)?;
+  let emitter = player.signal_emitter();
+  let mut iface = player.get_mut();
+  let expected = iface.current_position_millis();
+  let old = std::mem::replace(&mut iface.status, status);
+  iface.position_millis = position_millis;
+  iface.updated = Instant::now();
+  let new = iface.status.clone();
+  if old.playback_status != new.playback_status {
+    block_on(iface.playback_status_changed(emitter))?;
+  }
+  if old.play_modes != new.play_modes {
+    block_on(iface.loop_status_changed(emitter))?;
+    block_on(iface.shuffle_changed(emitter))?;
+  }
+  if (&old.item_id, &old.title, &old.artist, &old.album, &old.art_url, old.length_millis)
+    != (&new.item_id, &new.title, &new.artist, &new.album, &new.art_url, new.length_millis)
+  {
+    block_on(iface.metadata_changed(emitter))?;
+  } else if new.can_seek && (position_millis - expected).abs() > SEEK_THRESHOLD_MILLIS {
+    block_on(Player::seeked(emitter, position_millis * 1000))?;
+  }
+  if old.volume != new.volume {
+    block_on(iface.volume_changed(emitter))?;
+  }
+  if old.can_go_next != new.can_go_next {
+    block_on(iface.can_go_next_changed(emitter))?;
+  }
+  if old.can_go_previous != new.can_go_previous {
+    block_on(iface.can_go_previous_changed(emitter))?;
+  }
+  if old.can_play != new.can_play {
+    block_on(iface.can_play_changed(emitter))?;
+  }
+  if old.can_pause != new.can_pause {
+    block_on(iface.can_pause_changed(emitter))?;
+  }
+  if old.can_seek != new.can_seek {
+    block_on(iface.can_seek_changed(emitter))?;
+  }
+  drop(iface);
+  if old.identity != new.identity {
+    let root = server.interface::<_, Root>(PATH)?;
+    let mut iface = root.get_mut();
+    iface.identity = new.identity;
+    block_on(iface.identity_changed(root.signal_emitter()))?;
+  }
+  Ok(())
+}
+
+/// Execute `command` on the group coordinated by `coordinator`.
+///
+/// Commands like play/pause depend on the current state, so the group is
+/// looked up again instead of relying on the last poll.
+fn execute(
+  sonos: &mut Sonos,
+  household: &Household,
+  coordinator: &PlayerId,
+  command: Command
+) -> Result<()> {
+  let targets = HouseholdSnapshot::from(sonos.get_groups(household)?);
+  let group = targets
+    .coordinated_by(coordinator)
+    .ok_or("Group has been dissolved")?;
+  match command {
+    Command::Play => sonos.play(group)?,
+    Command::Pause => sonos.pause_or_stop(group)?,
+    Command::PlayPause => match group.playback_state {
+      PlaybackState::Playing | PlaybackState::Buffering => sonos.pause_or_stop(group)?,
+      _ => sonos.play(group)?
+    },
+    Command::Stop => {
+      if group.playback_state != PlaybackState::Idle {
+        sonos.stop(group)?
+      }
+    }
+    Command::Next => sonos.skip_to_next_track(group)?,
+    Command::Previous => sonos.skip_to_previous_track(group)?,
+    Command::Seek(offset) => sonos.seek_relative(group, i128::from(offset / 1000), None)?,
+    Command::SetPosition(position) => {
+      sonos.seek(group, u128::try_from(position / 1000).unwrap_or(0), None)?
+    }
+    Command::Volume(volume) => {
+      sonos.set_group_volume(group, (volume.clamp(0.0, 1.0) * 100.0).round() as u8)?
+    }
+    Command::PlayModes(play_modes) => sonos.set_play_modes(group, &play_modes)?
+  }
+  Ok(())
+}
+
+/// The bus name for a group coordinated by the player `name` with `id`.
+///
+/// Distinct names can map to the same bus name, like "Küche" and "K?che".
+/// Should the bus name already be taken, the ID of the coordinator is
+/// appended to tell them apart.
+fn bus_name<'a>(name: &str, id: &PlayerId, mut taken: impl Iterator<Item = &'a str>) -> String {
+  let name = format!("org.mpris.MediaPlayer2.ronor.{}", bus_name_element(name));
+  if taken.any(|taken| taken == name) {
+    format!("{}_{}", name, bus_name_element(&id.to_string()))
+  } else {
+    name
+  }
+}
+
+/// Turn `name` into something usable as an element of a bus name.
+fn bus_name_element(name: &str) -> String {
+  let mut element: String = name
+    .chars()
+    .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+    .collect();
+  if !element.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+    element.insert(0, '_');
+  }
+  element
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn bus_name_elements_are_valid() {
+    assert_eq!(bus_name_element("Living Room"), "Living_Room");
+    assert_eq!(bus_name_element("Küche"), "K_che");
+    assert_eq!(bus_name_element("2nd Floor"), "_2nd_Floor");
+  }
+
+  #[test]
+  fn colliding_bus_names_get_the_coordinator_id() {
+    let id = PlayerId::new(String::from("RINCON_000E58A0123401400"));
+    assert_eq!(bus_name("Küche", &id, std::iter::empty()), "org.mpris.MediaPlayer2.ronor.K_che");
+    assert_eq!(
+      bus_name("K?che", &id, ["org.mpris.MediaPlayer2.ronor.K_che"].into_iter()),
+      "org.mpris.MediaPlayer2.ronor.K_che_RINCON_000E58A0123401400"
+    );
+  }
+}
@@ -0,0 +1,506 @@
+//! Publish the state of a household to an MQTT broker and accept commands.
+//!
+//! All topics live below a prefix, `ronor` by default:
+//!
+//! - `ronor/status`: `online` or `offline`
+//! - `ronor/group/<coordinator>/{name,state,container,track,artist,album,stream,volume,muted}`
+//! - `ronor/player/<player>/{group,volume,muted}`
+//!
+//! Groups are named after their coordinator, which stays the same while
+//! other players come and go.  Names which end up as the same topic level,
+//! like "Living Room" and "living-room", get the player ID appended.
+//! Every state topic is retained, and cleared once its group or player
+//! disappears.  Commands are accepted on `<topic>/set` for the `state` of a
+//! group (`PLAY`, `PAUSE`, `TOGGLE`, `STOP`, `NEXT` or `PREVIOUS`) as well as
+//! `volume` (0 to 100, or `+N`/`-N`) and `muted` (`ON` or `OFF`) of groups
+//! and players.
+//!
+//! Home Assistant has no MQTT media player platform, so discovery describes
+//! every group and player as a device made up of sensors, a volume number,
+//! a mute switch and, for groups, playback buttons.
+
+use crate::now_playing::fields;
+use crate::{ErrorKind, Result, ResultExt};
+use ronor::watch::Snapshot;
+use ronor::{Group, Household, HouseholdSnapshot, PlaybackState, Player, PlayerId, Sonos};
+use rumqttc::{Client, Event, LastWill, MqttOptions, Packet, QoS};
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+use std::sync::mpsc::{channel, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Group fields published as topics of their own.
+const GROUP_FIELDS: [&str; 6] = ["state", "container", "track", "artist", "album", "stream"];
+
+pub struct Options {
+  /// Prefix of all topics.
+  pub prefix: String,
+  /// Where Home Assistant looks for discovery payloads, `None` to skip them.
+  pub discovery_prefix: Option<String>,
+  /// How long to wait between two snapshots.
+  pub interval: Duration
+}
+
+enum Message {
+  Connected,
+  Command(String, String)
+}
+
+/// Bridge `household` to the broker described by `mqtt` until something
+/// goes badly wrong.
+pub fn serve(
+  sonos: &mut Sonos,
+  household: &Household,
+  mut mqtt: MqttOptions,
+  options: &Options
+) -> Result<()> {
+  let status = format!("{}/status", options.prefix);
+  mqtt.set_last_will(LastWill::new(&status, "offline", QoS::AtLeastOnce, true));
+  let (client, mut connection) = Client::new(mqtt, 64);
+  let (messages, receiver) = channel();
+  let subscriber = client.clone();
+  let filter = format!("{}/+/+/+/set", options.prefix);
+  thread::spawn(move || {
+    for event in connection.iter() {
+      match event {
+        Ok(Event::Incoming(Packet::ConnAck(_))) => {
+          // Blocking here would keep the connection from making progress.
+          if let Err(e) = subscriber.try_subscribe(filter.as_str(), QoS::AtLeastOnce) {
+            eprintln!("Failed to subscribe to {}: {}", filter, e);
+          }
+          let _ = messages.send(Message::Connected);
+        }
+        Ok(Event::Incoming(Packet::Publish(publish))) => {
+          let payload = String::from_utf8_lossy(&publish.payload).to_string();
+          let _ = messages.send(Message::Command(publish.topic, payload));
+        }
+        Ok(_) => {}
+        Err(e) => {
+          eprintln!("MQTT: {}", e);
+          thread::sleep(Duration::from_secs(5));
+        }
+      }
+    }
+  });
+  let mut published: HashMap<String, String> = HashMap::new();
+  let mut polled = false;
+  let mut connected = false;
+  loop {
+    if connected {
+      match Snapshot::take(sonos, household, true) {
+        Ok(snapshot) => {
+          let wanted = topics(&snapshot, options);
+          publish(&client, &mut published, wanted)?;
+          polled = true;
+        }
+        Err(e) if polled => eprintln!("{}", e),
+        Err(e) => return Err(e.into())
+      }
+    }
+    let deadline = Instant::now() + options.interval;
+    match receiver.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+      Ok(Message::Connected) => {
+        // The broker might have lost retained messages, so start over.
+        published.clear();
+        client
+          .publish(status.as_str(), QoS::AtLeastOnce, true, "online")
+          .chain_err(|| "Failed to publish status")?;
+        connected = true;
+      }
+      Ok(Message::Command(topic, payload)) => {
+        if let Err(e) = execute(sonos, household, &options.prefix, &topic, &payload) {
+          eprintln!("{}: {}", topic, e);
+        }
+      }
+      Err(RecvTimeoutError::Timeout) => {}
+      Err(RecvTimeoutError::Disconnected) => return Err("Lost connection to broker".into())
+    }
+  }
+}
+
+/// Publish whatever differs from `published`, and clear topics which are
+/// no longer wanted.
+fn publish(
+  client: &Client,
+  published: &mut HashMap<String, String>,
+  wanted: HashMap<String, String>
+) -> Result<()> {
+  let gone: Vec<String> = published
+    .keys()
+    .filter(|topic| !wanted.contains_key(*topic))
+    .cloned()
+    .collect();
+  for topic in gone {
+    client
+      .publish(topic.as_str(), QoS::AtLeastOnce, true, "")
+      .chain_err(|| format!("Failed to clear {}", topic))?;
+    published.remove(&topic);
+  }
+  for (topic, payload) in wanted {
+    if published.get(&topic) != Some(&payload) {
+      client
+        .publish(topic.as_str(), QoS::AtLeastOnce, true, payload.as_str())
+        .chain_err(|| format!("Failed to publish {}", topic))?;
+      published.insert(topic, payload);
+    }
+  }
+  Ok(())
+}
+
+/// Every retained topic with its payload, including discovery.
+fn topics(snapshot: &Snapshot, options: &Options) -> HashMap<String, String> {
+  let mut topics = HashMap::new();
+  let household = &snapshot.household;
+  let slugs = slugs(household);
+  for group in household.groups().iter() {
+    let (playback, metadata) = match (
+      snapshot.playback.get(&group.id),
+      snapshot.metadata.get(&group.id)
+    ) {
+      (Some(playback), Some(metadata)) => (playback, metadata),
+      _ => continue
+    };
+    let level = group_slug(household, &slugs, group);
+    let base = format!("{}/group/{}", options.prefix, level);
+    let fields = fields(group, playback, metadata);
+    topics.insert(format!("{}/name", base), group.name.clone());
+    for field in GROUP_FIELDS.iter() {
+      topics.insert(
+        format!("{}/{}", base, field),
+        fields.get(field).cloned().unwrap_or_default()
+      );
+    }
+    if let Some(volume) = snapshot.group_volumes.get(&group.id) {
+      topics.insert(format!("{}/volume", base), volume.volume.to_string());
+      topics.insert(format!("{}/muted", base), on_off(volume.muted));
+    }
+    if let Some(discovery_prefix) = &options.discovery_prefix {
+      let coordinator = household
+        .coordinator(group)
+        .map_or(group.name.as_str(), |player| player.name.as_str());
+      let device =
+        Device::new(discovery_prefix, &options.prefix, "group", &level, coordinator);
+      for (field, name) in [
+        ("state", "State"),
+        ("container", "Source"),
+        ("track", "Track"),
+        ("artist", "Artist"),
+        ("album", "Album"),
+        ("stream", "Stream")
+      ] {
+        device.sensor(&mut topics, field, name);
+      }
+      device.volume(&mut topics);
+      for (payload, name) in [
+        ("PLAY", "Play"),
+        ("PAUSE", "Pause"),
+        ("NEXT", "Next"),
+        ("PREVIOUS", "Previous")
+      ] {
+        device.add(&mut topics, "button", &payload.to_lowercase(), json!({
+          "name": name,
+          "command_topic": format!("{}/state/set", device.base),
+          "payload_press": payload
+        }));
+      }
+    }
+  }
+  for player in household.players().iter() {
+    let level = &slugs[&player.id];
+    let base = format!("{}/player/{}", options.prefix, level);
+    if let Some(group) = household.group_of(&player.id) {
+      topics.insert(format!("{}/group", base), group.name.clone());
+    }
+    if let Some(volume) = snapshot.player_volumes.get(&player.id) {
+      topics.insert(format!("{}/volume", base), volume.volume.to_string());
+      topics.insert(format!("{}/muted", base), on_off(volume.muted));
+    }
+    if let Some(discovery_prefix) = &options.discovery_prefix {
+      let device =
+        Device::new(discovery_prefix, &options.prefix, "player", level, &player.name);
+      device.sensor(&mut topics, "group", "Group");
+      device.volume(&mut topics);
+    }
+  }
+  topics
+}
+
+/// Home Assistant discovery for one group or player.
+struct Device {
+  discovery_prefix: String,
+  availability: String,
+  base: String,
+  id: String,
+  info: Value
+}
+
+impl Device {
+  fn new(discovery_prefix: &str, prefix: &str, kind: &str, slug: &str, name: &str) -> Self {
+    let id = format!("ronor_{}_{}", kind, slug);
+    Device {
+      discovery_prefix: discovery_prefix.to_string(),
+      availability: format!("{}/status", prefix),
+      base: format!("{}/{}/{}", prefix, kind, slug),
+      info: json!({ "identifiers": [id], "name": name, "manufacturer": "Sonos" }),
+      id
+    }
+  }
+
+  fn add(
+    &self,
+    topics: &mut HashMap<String, String>,
+    component: &str,
+    object: &str,
+    mut config: Value
+  ) {
+    let unique_id = format!("{}_{}", self.id, object);
+    config["unique_id"] = json!(unique_id);
+    config["availability_topic"] = json!(self.availability);
+    config["device"] = self.info.clone();
+    topics.insert(
+      format!("{}/{}/{}/config", self.discovery_prefix, component, unique_id),
+      config.to_string()
+    );
+  }
+
+  fn sensor(&self, topics: &mut HashMap<String, String>, field: &str, name: &str) {
+    let state_topic = format!("{}/{}", self.base, field);
+    self.add(topics, "sensor", field, json!({ "name": name, "state_topic": state_topic }));
+  }
+
+  /// A volume number and a mute switch.
+  fn volume(&self, topics: &mut HashMap<String, String>) {
+    let volume = format!("{}/volume", self.base);
+    let muted = format!("{}/muted", self.base);
+    self.add(topics, "number", "volume", json!({
+      "name": "Volume",
+      "state_topic": volume,
+      "command_topic": format!("{}/set", volume),
+      "min": 0,
+      "max": 100,
+      "step": 1
+    }));
+    self.add(topics, "switch", "muted", json!({
+      "name": "Mute",
+      "state_topic": muted,
+      "command_topic": format!("{}/set", muted),
+      "payload_on": "ON",
+      "payload_off": "OFF"
+    }));
+  }
+}
+
+enum Volume {
+  Absolute(u8),
+  Relative(i8)
+}
+
+/// Execute a command published to `topic`.
+///
+/// Group ids change with membership and the playback state might have
+/// changed since the last poll, so the household is looked up again.
+fn execute(
+  sonos: &mut Sonos,
+  household: &Household,
+  prefix: &str,
+  topic: &str,
+  payload: &str
+) -> Result<()> {
+  let payload = payload.trim();
+  let parts: Vec<&str> = topic
+    .strip_prefix(prefix)
+    .and_then(|topic| topic.strip_prefix('/'))
+    .map(|topic| topic.split('/').collect())
+    .unwrap_or_default();
+  let household = HouseholdSnapshot::from(sonos.get_groups(household)?);
+  let slugs = slugs(&household);
+  match parts.as_slice() {
+    ["group", name, attribute, "set"] => {
+      let group = household
+        .groups()
+        .iter()
+        .find(|group| group_slug(&household, &slugs, group) == *name)
+        .ok_or_else(|| ErrorKind::UnknownGroup(name.to_string()))?;
+      match *attribute {
+        "state" => match payload.to_uppercase().as_str() {
+          "PLAY" => sonos.play(group)?,
+          "PAUSE" => sonos.pause_or_stop(group)?,
+          "TOGGLE" => match group.playback_state {
+            PlaybackState::Playing | PlaybackState::Buffering => sonos.pause_or_stop(group)?,
+            _ => sonos.play(group)?
+          },
+          "STOP" => {
+            if group.playback_state != PlaybackState::Idle {
+              sonos.stop(group)?
+            }
+          }
+          "NEXT" => sonos.skip_to_next_track(group)?,
+          "PREVIOUS" => sonos.skip_to_previous_track(group)?,
+          _ => return Err(format!("Unknown playback command '{}'", payload).into())
+        },
+        "volume" => match parse_volume(payload)? {
+          Volume::Absolute(volume) => sonos.set_group_volume(group, volume)?,
+          Volume::Relative(delta) => sonos.set_relative_group_volume(group, delta)?
+        },
+        "muted" => sonos.set_group_mute(group, parse_on_off(payload)?)?,
+        _ => return Err(format!("Unknown command topic '{}'", topic).into())
+      }
+    }
+    ["player", name, attribute, "set"] => {
+      let player = household
+        .players()
+        .iter()
+        .find(|player| slugs[&player.id] == *name)
+        .ok_or_else(|| ErrorKind::UnknownPlayer(name.to_string()))?;
+      match *attribute {
+        "volume" => match parse_volume(payload)? {
+          Volume::Absolute(volume) => sonos.set_player_volume(player, volume)?,
+          Volume::Relative(delta) => sonos.set_relative_player_volume(player, delta)?
+        },
+        "muted" => sonos.set_player_mute(player, parse_on_off(payload)?)?,
+        _ => return Err(format!("Unknown command topic '{}'", topic).into())
+      }
+    }
+    _ => return Err(format!("Unknown command topic '{}'", topic).into())
+  }
+  Ok(())
+}
+
+/// Topic levels for all players, unique within the household.
+///
+/// Names can map to the same level, like "Living Room" and "living-room".
+/// Players are taken in order of their IDs, later ones in a collision get
+/// their ID appended, so levels do not depend on the order Sonos lists them in.
+fn slugs(household: &HouseholdSnapshot) -> HashMap<PlayerId, String> {
+  let mut players: Vec<&Player> = household.players().iter().collect();
+  players.sort_by_key(|player| player.id.to_string());
+  let mut taken = HashSet::new();
+  let mut slugs = HashMap::new();
+  for player in players {
+    let mut level = slug(&player.name);
+    if !taken.insert(level.clone()) {
+      level = format!("{}_{}", level, slug(&player.id.to_string()));
+      taken.insert(level.clone());
+    }
+    slugs.insert(player.id.clone(), level);
+  }
+  slugs
+}
+
+/// Groups are named after their coordinator, which stays the same while
+/// other players come and go.
+fn group_slug(
+  household: &HouseholdSnapshot,
+  slugs: &HashMap<PlayerId, String>,
+  group: &Group
+) -> String {
+  match household.coordinator(group) {
+    Some(coordinator) => slugs[&coordinator.id].clone(),
+    None => slug(&group.name)
+  }
+}
+
+/// Turn `name` into a topic level which is also valid in discovery IDs.
+fn slug(name: &str) -> String {
+  name
+    .to_lowercase()
+    .chars()
+    .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+    .collect()
+}
+
+fn on_off(on: bool) -> String {
+  String::from(if on { "ON" } else { "OFF" })
+}
+
+fn parse_on_off(payload: &str) -> Result<bool> {
+  match payload.to_uppercase().as_str() {
+    "ON" | "TRUE" | "1" => Ok(true),
+    "OFF" | "FALSE" | "0" => Ok(false),
+    _ => Err(format!("Expected ON or OFF, got '{}'", payload).into())
+  }
+}
+
+fn parse_volume(payload: &str) -> Result<Volume> {
+  if payload.starts_with(['+', '-']) {
+    let delta = payload
+      .parse::<i8>()
+      .chain_err(|| format!("Invalid volume change '{}'", payload))?;
+    return Ok(Volume::Relative(delta));
+  }
+  // Home Assistant sends numbers like "42.0".
+  match payload.parse::<f64>() {
+    Ok(volume) if (0.0..=100.0).contains(&volume) => Ok(Volume::Absolute(volume.round() as u8)),
+    _ => Err(format!("Volume has to be between 0 and 100, got '{}'", payload).into())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use ronor::Groups;
+  use serde_json::json;
+
+  fn household(players: &[(&str, &str)]) -> HouseholdSnapshot {
+    let groups: Vec<Value> = players
+      .iter()
+      .map(|(id, name)| {
+        json!({
+          "coordinatorId": id,
+          "id": format!("G{}", id),
+          "playbackState": "PLAYBACK_STATE_IDLE",
+          "playerIds": [id],
+          "name": name
+        })
+      })
+      .collect();
+    let players: Vec<Value> = players
+      .iter()
+      .map(|(id, name)| {
+        json!({
+          "isUnregistered": false,
+          "apiVersion": "1.1.0",
+          "deviceIds": [id],
+          "id": id,
+          "minApiVersion": "1.1.0",
+          "name": name,
+          "softwareVersion": "1.0",
+          "capabilities": ["PLAYBACK"],
+          "websocketUrl": "wss://localhost"
+        })
+      })
+      .collect();
+    let groups: Groups =
+      serde_json::from_value(json!({ "groups": groups, "players": players, "partial": false }))
+        .unwrap();
+    HouseholdSnapshot::from(groups)
+  }
+
+  #[test]
+  fn slugs_are_topic_levels() {
+    assert_eq!(slug("Wohnzimmer"), "wohnzimmer");
+    assert_eq!(slug("Living Room"), "living_room");
+    assert_eq!(slug("Küche/2"), "k_che_2");
+  }
+
+  #[test]
+  fn colliding_slugs_get_the_player_id() {
+    let household = household(&[
+      ("RINCON_B", "living-room"),
+      ("RINCON_A", "Living Room"),
+      ("RINCON_C", "Bad")
+    ]);
+    let slugs = slugs(&household);
+    let id = |id: &str| PlayerId::new(id.to_string());
+    assert_eq!(slugs[&id("RINCON_A")], "living_room");
+    assert_eq!(slugs[&id("RINCON_B")], "living_room_rincon_b");
+    assert_eq!(slugs[&id("RINCON_C")], "bad");
+    let groups: HashSet<String> = household
+      .groups()
+      .iter()
+      .map(|group| group_slug(&household, &slugs, group))
+      .collect();
+    assert_eq!(groups.len(), 3);
+  }
+}
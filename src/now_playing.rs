@@ -0,0 +1,114 @@
+//! What a group is playing, as a flat map of named fields.
+//!
+//! Shared by `now-playing` templates, the MQTT bridge and the HTTP gateway.
+
+use ronor::{Group, Item, MetadataStatus, PlaybackState, PlaybackStatus, Tag};
+use std::collections::HashMap;
+
+/// Fields available in `--format` templates.
+pub const FIELDS: [&str; 17] = [
+  "group", "state", "container", "service", "track", "album", "artist", "author",
+  "narrator", "stream", "position", "duration", "progress", "quality", "explicit",
+  "next", "item"
+];
+
+/// Collect everything worth knowing about what `group` is playing.
+///
+/// Missing information is left out.
+pub fn fields(
+  group: &Group,
+  playback_status: &PlaybackStatus,
+  metadata_status: &MetadataStatus
+) -> HashMap<&'static str, String> {
+  let mut fields = HashMap::new();
+  fields.insert("group", group.name.clone());
+  fields.insert(
+    "state",
+    String::from(match playback_status.playback_state {
+      PlaybackState::Idle => "idle",
+      PlaybackState::Paused => "paused",
+      PlaybackState::Buffering => "buffering",
+      PlaybackState::Playing => "playing"
+    })
+  );
+  if let Some(container) = &metadata_status.container {
+    if container.type_.as_deref() == Some("linein.homeTheater") {
+      fields.insert("container", String::from("Home theater"));
+    } else {
+      if let Some(name) = &container.name {
+        fields.insert("container", name.clone());
+      }
+      if let Some(service) = &container.service {
+        fields.insert("service", service.name.clone());
+      }
+    }
+  }
+  if let Some(item) = &metadata_status.current_item {
+    let track = &item.track;
+    if let Some(id) = &item.id {
+      fields.insert("item", id.clone());
+    }
+    if let Some(name) = &track.name {
+      fields.insert("track", name.clone());
+    }
+    if let Some(album) = &track.album {
+      fields.insert("album", album.name.clone());
+    }
+    if let Some(artist) = &track.artist {
+      fields.insert("artist", artist.name.clone());
+    }
+    if let Some(author) = &track.author {
+      fields.insert("author", author.name.clone());
+    }
+    if let Some(narrator) = &track.narrator {
+      fields.insert("narrator", narrator.name.clone());
+    }
+    if let Some(service) = &track.service {
+      fields.insert("service", service.name.clone());
+    }
+    let position = playback_status.position_millis.max(0);
+    fields.insert("position", format_millis(position));
+    if let Some(duration) = track.duration_millis.filter(|duration| *duration > 0) {
+      let duration = i64::from(duration);
+      fields.insert("duration", format_millis(duration));
+      fields.insert("progress", format!("{}%", (position * 100 / duration).min(100)));
+    }
+    if track.quality.bit_depth > 0.0 && track.quality.sample_rate > 0.0 {
+      fields.insert(
+        "quality",
+        format!(
+          "{}-bit {}kHz",
+          track.quality.bit_depth,
+          track.quality.sample_rate / 1000.0
+        )
+      );
+    }
+    if track.explicit || track.tags.contains(&Tag::Explicit) {
+      fields.insert("explicit", String::from("explicit"));
+    }
+  }
+  if let Some(next) = &metadata_status.next_item {
+    fields.insert("next", item_name(next));
+  }
+  if let Some(stream_info) = &metadata_status.stream_info {
+    fields.insert("stream", stream_info.trim().trim_matches('-').trim().to_string());
+  }
+  fields
+}
+
+fn item_name(item: &Item) -> String {
+  let mut name = item.track.name.clone().unwrap_or_default();
+  if let Some(artist) = &item.track.artist {
+    name += &format!(" - {}", artist.name);
+  }
+  name
+}
+
+fn format_millis(millis: i64) -> String {
+  let seconds = millis / 1000;
+  if seconds >= 3600 {
+    format!("{}:{:02}:{:02}", seconds / 3600, seconds / 60 % 60, seconds % 60)
+  } else {
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+  }
+}
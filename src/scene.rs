@@ -0,0 +1,223 @@
+//! Snapshots of a whole household, stored as TOML in the `scenes` directory
+//! of the configuration.
+//!
+//! Players are referred to by name, so scenes can be written by hand.
+
+use crate::{ErrorKind, Result, ResultExt};
+use ronor::{Household, HouseholdSnapshot, PlayModes, PlaybackState, PlayerId, Sonos};
+use serde::{Deserialize, Serialize};
+use std::fs::{read_dir, read_to_string, write};
+use std::path::PathBuf;
+use xdg::BaseDirectories;
+
+#[derive(Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Scene {
+  #[serde(default, rename = "group")]
+  pub groups: Vec<GroupState>,
+  #[serde(default, rename = "player")]
+  pub players: Vec<PlayerState>
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct GroupState {
+  pub coordinator: String,
+  /// All members of the group, including the coordinator.
+  pub players: Vec<String>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub volume: Option<u8>,
+  #[serde(default)]
+  pub muted: bool,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub favorite: Option<String>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub playlist: Option<String>,
+  #[serde(default)]
+  pub playing: bool,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub play_modes: Option<PlayModes>
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct PlayerState {
+  pub name: String,
+  /// Missing for players with fixed volume.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub volume: Option<u8>,
+  #[serde(default)]
+  pub muted: bool
+}
+
+impl Scene {
+  fn directory() -> Result<PathBuf> {
+    Ok(BaseDirectories::with_prefix("ronor")?.create_config_directory("scenes")?)
+  }
+
+  fn path(name: &str) -> Result<PathBuf> {
+    if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) {
+      return Err(format!("Invalid scene name '{}'", name).into());
+    }
+    Ok(Scene::directory()?.join(format!("{}.toml", name)))
+  }
+
+  /// Names of all saved scenes, sorted alphabetically.
+  pub fn names() -> Result<Vec<String>> {
+    let mut names = Vec::new();
+    for entry in read_dir(Scene::directory()?)? {
+      let path = entry?.path();
+      if path.extension().is_some_and(|extension| extension == "toml") {
+        if let Some(name) = path.file_stem().and_then(|stem| stem.to_str()) {
+          names.push(name.to_string());
+        }
+      }
+    }
+    names.sort();
+    Ok(names)
+  }
+
+  pub fn load(name: &str) -> Result<Self> {
+    let path = Scene::path(name)?;
+    if !path.exists() {
+      return Err(format!("No scene named '{}'", name).into());
+    }
+    toml::from_str(&read_to_string(&path)?)
+      .chain_err(|| format!("Failed to parse '{}'", path.display()))
+  }
+
+  pub fn save(&self, name: &str) -> Result<()> {
+    write(
+      Scene::path(name)?,
+      toml::to_string_pretty(self).chain_err(|| "Failed to serialize scene")?
+    )?;
+    Ok(())
+  }
+
+  /// Record grouping, volumes and loaded content of all groups in a household.
+  pub fn capture(sonos: &mut Sonos, household: &Household) -> Result<Self> {
+    let targets = HouseholdSnapshot::from(sonos.get_groups(household)?);
+    let favorites = sonos.get_favorites(household)?.items;
+    let playlists = sonos.get_playlists(household)?.playlists;
+    let mut scene = Scene::default();
+    for group in targets.groups().iter() {
+      let coordinator = match targets.coordinator(group) {
+        Some(player) => player.name.clone(),
+        None => continue
+      };
+      let volume = sonos.get_group_volume(group)?;
+      let playback = sonos.get_playback_status(group)?;
+      let container = sonos.get_metadata_status(group)?.container;
+      let container_name = container.and_then(|container| container.name);
+      let favorite = container_name
+        .clone()
+        .filter(|name| favorites.iter().any(|favorite| &favorite.name == name));
+      let playlist = container_name.filter(|name| {
+        favorite.is_none() && playlists.iter().any(|playlist| &playlist.name == name)
+      });
+      let loaded = favorite.is_some() || playlist.is_some();
+      scene.groups.push(GroupState {
+        coordinator,
+        players: targets.members(group).map(|player| player.name.clone()).collect(),
+        volume: if volume.fixed { None } else { Some(volume.volume) },
+        muted: volume.muted,
+        playing: loaded && playback.playback_state == PlaybackState::Playing,
+        play_modes: loaded.then_some(playback.play_modes),
+        favorite,
+        playlist
+      });
+    }
+    for player in targets.players().iter() {
+      let volume = sonos.get_player_volume(player)?;
+      scene.players.push(PlayerState {
+        name: player.name.clone(),
+        volume: if volume.fixed { None } else { Some(volume.volume) },
+        muted: volume.muted
+      });
+    }
+    Ok(scene)
+  }
+
+  /// Regroup players and re-apply volumes and content.
+  ///
+  /// Players which are not part of the scene are left alone.
+  pub fn restore(&self, sonos: &mut Sonos, household: &Household) -> Result<()> {
+    let favorites = sonos.get_favorites(household)?.items;
+    let playlists = sonos.get_playlists(household)?.playlists;
+    for state in self.groups.iter() {
+      let mut targets = HouseholdSnapshot::from(sonos.get_groups(household)?);
+      let coordinator = targets
+        .player_by_name(&state.coordinator)
+        .ok_or_else(|| ErrorKind::UnknownPlayer(state.coordinator.clone()))?
+        .id
+        .clone();
+      // The coordinator has to lead a group before others can be added to it.
+      if let Some(group) = targets
+        .group_of(&coordinator)
+        .filter(|group| group.coordinator_id != coordinator)
+      {
+        sonos.modify_group_members(group, &[], &[&coordinator])?;
+        targets = HouseholdSnapshot::from(sonos.get_groups(household)?);
+      }
+      let group = targets
+        .coordinated_by(&coordinator)
+        .ok_or("Coordinator does not lead a group")?;
+      let mut wanted = Vec::new();
+      for name in state.players.iter() {
+        let player = targets
+          .player_by_name(name)
+          .ok_or_else(|| ErrorKind::UnknownPlayer(name.to_string()))?;
+        wanted.push(&player.id);
+      }
+      let add: Vec<&PlayerId> = wanted
+        .iter()
+        .filter(|id| !group.player_ids.contains(id))
+        .copied()
+        .collect();
+      let remove: Vec<&PlayerId> = group
+        .player_ids
+        .iter()
+        .filter(|id| !wanted.contains(id))
+        .collect();
+      if !add.is_empty() || !remove.is_empty() {
+        sonos.modify_group_members(group, &add, &remove)?;
+      }
+    }
+    let targets = HouseholdSnapshot::from(sonos.get_groups(household)?);
+    for state in self.groups.iter() {
+      let coordinator = targets
+        .player_by_name(&state.coordinator)
+        .ok_or_else(|| ErrorKind::UnknownPlayer(state.coordinator.clone()))?;
+      let group = targets
+        .coordinated_by(&coordinator.id)
+        .ok_or("Coordinator does not lead a group")?;
+      if let Some(volume) = state.volume {
+        sonos.set_group_volume(group, volume)?;
+      }
+      sonos.set_group_mute(group, state.muted)?;
+      if let Some(name) = &state.favorite {
+        let favorite = favorites
+          .iter()
+          .find(|favorite| &favorite.name == name)
+          .ok_or_else(|| ErrorKind::UnknownFavorite(name.to_string()))?;
+        sonos.load_favorite(group, favorite, state.playing, state.play_modes.as_ref())?;
+      } else if let Some(name) = &state.playlist {
+        let playlist = playlists
+          .iter()
+          .find(|playlist| &playlist.name == name)
+          .ok_or_else(|| ErrorKind::UnknownPlaylist(name.to_string()))?;
+        sonos.load_playlist(group, playlist, state.playing, state.play_modes.as_ref())?;
+      }
+    }
+    for state in self.players.iter() {
+      let player = targets
+        .player_by_name(&state.name)
+        .ok_or_else(|| ErrorKind::UnknownPlayer(state.name.clone()))?;
+      if let Some(volume) = state.volume {
+        sonos.set_player_volume(player, volume)?;
+      }
+      sonos.set_player_mute(player, state.muted)?;
+    }
+    Ok(())
+  }
+}
@@ -0,0 +1,415 @@
+//! Time based actions, stored in `schedule.toml` in the configuration
+//! directory.
+//!
+//! Every entry fires either according to a cron expression
+//! (`minute hour day-of-month month day-of-week`) or at a `time` of day on
+//! certain `days` of the week.
+
+use crate::{Result, ResultExt};
+use chrono::{DateTime, Datelike, Local, TimeDelta, Timelike};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::{read_to_string, write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use xdg::BaseDirectories;
+
+const WEEKDAYS: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+const MONTHS: [&str; 12] = [
+  "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec"
+];
+
+#[derive(Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Schedule {
+  #[serde(default, rename = "entry")]
+  pub entries: Vec<Entry>
+}
+
+impl Schedule {
+  pub fn path() -> Result<PathBuf> {
+    Ok(BaseDirectories::with_prefix("ronor")?.place_config_file("schedule.toml")?)
+  }
+
+  /// Read a schedule, a missing file is an empty schedule.
+  pub fn load(path: &Path) -> Result<Self> {
+    if !path.exists() {
+      return Ok(Schedule::default());
+    }
+    let schedule: Schedule = toml::from_str(&read_to_string(path)?)
+      .chain_err(|| format!("Failed to parse '{}'", path.display()))?;
+    for entry in schedule.entries.iter() {
+      entry
+        .cron()
+        .chain_err(|| format!("Invalid schedule for '{}'", entry.name))?;
+    }
+    Ok(schedule)
+  }
+
+  pub fn save(&self, path: &Path) -> Result<()> {
+    write(
+      path,
+      toml::to_string_pretty(self).chain_err(|| "Failed to serialize schedule")?
+    )?;
+    Ok(())
+  }
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct Entry {
+  pub name: String,
+  /// A cron expression, takes precedence over `time` and `days`.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub cron: Option<String>,
+  /// Time of day as `HH:MM`.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub time: Option<String>,
+  /// Days of the week like `mon-fri` or `sat,sun`, every day if missing.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub days: Option<String>,
+  #[serde(flatten)]
+  pub action: Action
+}
+
+impl Entry {
+  pub fn cron(&self) -> Result<Cron> {
+    match (&self.cron, &self.time) {
+      (Some(cron), _) => cron.parse(),
+      (None, Some(time)) => {
+        let (hour, minute) = time
+          .split_once(':')
+          .ok_or_else(|| format!("Time '{}' is not in HH:MM format", time))?;
+        format!(
+          "{} {} * * {}",
+          minute.parse::<u8>()?,
+          hour.parse::<u8>()?,
+          self.days.as_deref().unwrap_or("*")
+        )
+        .parse()
+      }
+      (None, None) => Err("Either cron or time is required".into())
+    }
+  }
+
+  pub fn is_due(&self, now: &DateTime<Local>) -> bool {
+    self.cron().is_ok_and(|cron| cron.matches(now))
+  }
+}
+
+impl fmt::Display for Entry {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}: ", self.name)?;
+    match (&self.cron, &self.time) {
+      (Some(cron), _) => write!(f, "cron '{}'", cron)?,
+      (None, Some(time)) => write!(
+        f,
+        "{} on {}",
+        time,
+        self.days.as_deref().unwrap_or("every day")
+      )?,
+      (None, None) => write!(f, "never")?
+    }
+    write!(f, ": {}", self.action)
+  }
+}
+
+fn yes() -> bool {
+  true
+}
+
+fn is_false(b: &bool) -> bool {
+  !b
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(tag = "action", rename_all = "kebab-case")]
+pub enum Action {
+  LoadFavorite {
+    group: String,
+    favorite: String,
+    #[serde(default = "yes")]
+    play: bool,
+    #[serde(default, skip_serializing_if = "is_false")]
+    repeat: bool,
+    #[serde(default, skip_serializing_if = "is_false")]
+    repeat_one: bool,
+    #[serde(default, skip_serializing_if = "is_false")]
+    crossfade: bool,
+    #[serde(default, skip_serializing_if = "is_false")]
+    shuffle: bool
+  },
+  LoadPlaylist {
+    group: String,
+    playlist: String,
+    #[serde(default = "yes")]
+    play: bool,
+    #[serde(default, skip_serializing_if = "is_false")]
+    repeat: bool,
+    #[serde(default, skip_serializing_if = "is_false")]
+    repeat_one: bool,
+    #[serde(default, skip_serializing_if = "is_false")]
+    crossfade: bool,
+    #[serde(default, skip_serializing_if = "is_false")]
+    shuffle: bool
+  },
+  SetVolume {
+    group: String,
+    volume: u8
+  },
+  Speak {
+    player: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    text: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    scrape: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    language: Option<String>
+  },
+  Pause {
+    group: String
+  }
+}
+
+impl fmt::Display for Action {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Action::LoadFavorite {
+        group, favorite, ..
+      } => write!(f, "load favorite '{}' in {}", favorite, group),
+      Action::LoadPlaylist {
+        group, playlist, ..
+      } => write!(f, "load playlist '{}' in {}", playlist, group),
+      Action::SetVolume { group, volume } => {
+        write!(f, "set volume of {} to {}", group, volume)
+      }
+      Action::Speak {
+        player,
+        text,
+        scrape,
+        ..
+      } => match (text, scrape) {
+        (_, Some(uri)) => write!(f, "speak {} on {}", uri, player),
+        (Some(text), None) => write!(f, "speak {:?} on {}", text, player),
+        (None, None) => write!(f, "speak nothing on {}", player)
+      },
+      Action::Pause { group } => write!(f, "pause {}", group)
+    }
+  }
+}
+
+/// A parsed cron expression.
+#[derive(Debug, PartialEq)]
+pub struct Cron {
+  minutes: u64,
+  hours: u64,
+  days: u64,
+  months: u64,
+  weekdays: u64,
+  days_restricted: bool,
+  weekdays_restricted: bool
+}
+
+impl Cron {
+  pub fn matches(&self, time: &DateTime<Local>) -> bool {
+    let bit = |set: u64, n: u32| set & (1 << n) != 0;
+    let day = bit(self.days, time.day());
+    let weekday = bit(self.weekdays, time.weekday().num_days_from_sunday());
+    let day = if self.days_restricted && self.weekdays_restricted {
+      day || weekday
+    } else {
+      day && weekday
+    };
+    bit(self.minutes, time.minute())
+      && bit(self.hours, time.hour())
+      && bit(self.months, time.month())
+      && day
+  }
+}
+
+impl FromStr for Cron {
+  type Err = crate::Error;
+  fn from_str(s: &str) -> Result<Self> {
+    let fields: Vec<&str> = s.split_whitespace().collect();
+    if fields.len() != 5 {
+      return Err(format!("Expected 5 fields in cron expression '{}'", s).into());
+    }
+    // Both 0 and 7 are Sunday.
+    let weekdays = field(fields[4], 0, 7, &WEEKDAYS)?;
+    let weekdays = (weekdays | weekdays >> 7) & 0x7f;
+    Ok(Cron {
+      minutes: field(fields[0], 0, 59, &[])?,
+      hours: field(fields[1], 0, 23, &[])?,
+      days: field(fields[2], 1, 31, &[])?,
+      months: field(fields[3], 1, 12, &MONTHS)?,
+      weekdays,
+      days_restricted: !fields[2].starts_with('*'),
+      weekdays_restricted: !fields[4].starts_with('*')
+    })
+  }
+}
+
+/// Parse one field of a cron expression into a bit set.
+///
+/// `names` are alternative spellings for the values starting at `min`.
+fn field(spec: &str, min: u32, max: u32, names: &[&str]) -> Result<u64> {
+  let value = |s: &str| -> Result<u32> {
+    let lower = s.to_lowercase();
+    match names.iter().position(|name| *name == lower) {
+      Some(index) => Ok(min + index as u32),
+      None => s
+        .parse::<u32>()
+        .chain_err(|| format!("Invalid value '{}' in cron field '{}'", s, spec))
+    }
+  };
+  let mut set = 0;
+  for part in spec.split(',') {
+    let (range, step) = match part.split_once('/') {
+      Some((range, step)) => (range, Some(step.parse::<u32>()?)),
+      None => (part, None)
+    };
+    let (first, last) = if range == "*" {
+      (min, max)
+    } else if let Some((first, last)) = range.split_once('-') {
+      (value(first)?, value(last)?)
+    } else {
+      let first = value(range)?;
+      (first, if step.is_some() { max } else { first })
+    };
+    if first < min || last > max || first > last || step == Some(0) {
+      return Err(format!("Value out of range in cron field '{}'", spec).into());
+    }
+    for n in (first..=last).step_by(step.unwrap_or(1) as usize) {
+      set |= 1 << n;
+    }
+  }
+  Ok(set)
+}
+
+/// The minutes to run entries for at `now`, starting after `last`.
+///
+/// Minutes missed because an action took long or the machine was suspended
+/// are caught up, but only as far back as `catch_up`.  A wake-up alarm
+/// should not go off an hour late.
+pub fn due_minutes(
+  last: Option<DateTime<Local>>,
+  now: DateTime<Local>,
+  catch_up: TimeDelta
+) -> Vec<DateTime<Local>> {
+  let mut minute = match last {
+    Some(last) => (last + TimeDelta::minutes(1)).max(now - catch_up),
+    None => now
+  };
+  let mut minutes = Vec::new();
+  while minute <= now {
+    minutes.push(minute);
+    minute += TimeDelta::minutes(1);
+  }
+  minutes
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use chrono::TimeZone;
+
+  fn at(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> DateTime<Local> {
+    Local.with_ymd_and_hms(year, month, day, hour, minute, 0).unwrap()
+  }
+
+  fn bits(values: &[u32]) -> u64 {
+    values.iter().fold(0, |set, n| set | 1 << n)
+  }
+
+  #[test]
+  fn parses_fields() {
+    assert_eq!(field("*", 0, 6, &[]).unwrap(), bits(&[0, 1, 2, 3, 4, 5, 6]));
+    assert_eq!(field("5", 0, 59, &[]).unwrap(), bits(&[5]));
+    assert_eq!(field("1-3,7", 0, 59, &[]).unwrap(), bits(&[1, 2, 3, 7]));
+    assert_eq!(field("*/15", 0, 59, &[]).unwrap(), bits(&[0, 15, 30, 45]));
+    assert_eq!(field("10-20/5", 0, 59, &[]).unwrap(), bits(&[10, 15, 20]));
+    assert_eq!(field("50/4", 0, 59, &[]).unwrap(), bits(&[50, 54, 58]));
+    assert_eq!(field("mon-fri", 0, 7, &WEEKDAYS).unwrap(), bits(&[1, 2, 3, 4, 5]));
+    assert_eq!(field("Jan,DEC", 1, 12, &MONTHS).unwrap(), bits(&[1, 12]));
+  }
+
+  #[test]
+  fn rejects_invalid_fields() {
+    assert!(field("60", 0, 59, &[]).is_err());
+    assert!(field("0", 1, 31, &[]).is_err());
+    assert!(field("5-1", 0, 59, &[]).is_err());
+    assert!(field("*/0", 0, 59, &[]).is_err());
+    assert!(field("*/x", 0, 59, &[]).is_err());
+    assert!(field("noon", 0, 23, &[]).is_err());
+    assert!(field("1,", 0, 59, &[]).is_err());
+    assert!("* * * *".parse::<Cron>().is_err());
+    assert!("* * * * * *".parse::<Cron>().is_err());
+  }
+
+  #[test]
+  fn sunday_is_zero_and_seven() {
+    let zero: Cron = "0 8 * * 0".parse().unwrap();
+    let seven: Cron = "0 8 * * 7".parse().unwrap();
+    assert_eq!(zero, seven);
+    // 2024-06-02 was a Sunday.
+    assert!(seven.matches(&at(2024, 6, 2, 8, 0)));
+    assert!(!seven.matches(&at(2024, 6, 3, 8, 0)));
+  }
+
+  #[test]
+  fn matches_times() {
+    let cron: Cron = "30 7 * * mon-fri".parse().unwrap();
+    assert!(cron.matches(&at(2024, 6, 3, 7, 30)));
+    assert!(!cron.matches(&at(2024, 6, 3, 7, 31)));
+    assert!(!cron.matches(&at(2024, 6, 1, 7, 30)));
+  }
+
+  #[test]
+  fn day_of_month_or_day_of_week() {
+    // Like cron, a restricted day of month and day of week match either.
+    let cron: Cron = "0 12 1 * fri".parse().unwrap();
+    assert!(cron.matches(&at(2024, 6, 1, 12, 0)));
+    assert!(cron.matches(&at(2024, 6, 7, 12, 0)));
+    assert!(!cron.matches(&at(2024, 6, 8, 12, 0)));
+    let cron: Cron = "0 12 1 * *".parse().unwrap();
+    assert!(!cron.matches(&at(2024, 6, 7, 12, 0)));
+  }
+
+  #[test]
+  fn time_and_days() {
+    let entry: Entry = toml::from_str(
+      "name = \"wake\"\ntime = \"06:45\"\ndays = \"sat,sun\"\naction = \"pause\"\ngroup = \"Bad\""
+    )
+    .unwrap();
+    assert_eq!(entry.cron().unwrap(), "45 6 * * sat,sun".parse().unwrap());
+    assert!(entry.is_due(&at(2024, 6, 1, 6, 45)));
+    assert!(!entry.is_due(&at(2024, 6, 3, 6, 45)));
+  }
+
+  #[test]
+  fn runs_each_minute_once() {
+    let now = at(2024, 6, 1, 7, 0);
+    assert_eq!(due_minutes(None, now, TimeDelta::minutes(5)), vec![now]);
+    assert!(due_minutes(Some(now), now, TimeDelta::minutes(5)).is_empty());
+  }
+
+  #[test]
+  fn catches_up_on_missed_minutes() {
+    let minutes = due_minutes(
+      Some(at(2024, 6, 1, 6, 57)),
+      at(2024, 6, 1, 7, 0),
+      TimeDelta::minutes(5)
+    );
+    assert_eq!(minutes, vec![at(2024, 6, 1, 6, 58), at(2024, 6, 1, 6, 59), at(2024, 6, 1, 7, 0)]);
+  }
+
+  #[test]
+  fn caps_catching_up() {
+    let minutes = due_minutes(
+      Some(at(2024, 6, 1, 6, 0)),
+      at(2024, 6, 1, 7, 55),
+      TimeDelta::minutes(2)
+    );
+    assert_eq!(minutes, vec![at(2024, 6, 1, 7, 53), at(2024, 6, 1, 7, 54), at(2024, 6, 1, 7, 55)]);
+    assert!(!minutes.contains(&at(2024, 6, 1, 7, 0)));
+  }
+}
@@ -0,0 +1,405 @@
+//! A small subset of the [Speech Synthesis Markup Language].
+//!
+//! Documents are flattened into a list of segments, each spoken in a single
+//! language.  Pauses and emphasis are kept so they can be mapped onto the
+//! markup understood by the TTS engine.
+//!
+//! [Speech Synthesis Markup Language]: https://www.w3.org/TR/speech-synthesis11/
+
+use crate::{Result, ResultExt};
+use humantime::parse_duration;
+use roxmltree::{Document, Node};
+use std::time::Duration;
+
+const XML_NAMESPACE: &str = "http://www.w3.org/XML/1998/namespace";
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Part {
+  Text(String),
+  Emphasis(String, String),
+  Break(Duration)
+}
+
+impl Part {
+  fn len(&self) -> usize {
+    match self {
+      Part::Text(text) | Part::Emphasis(_, text) => text.chars().count(),
+      Part::Break(_) => 0
+    }
+  }
+}
+
+/// A run of speech in a single language.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Segment {
+  pub language: Option<String>,
+  pub parts: Vec<Part>
+}
+
+impl Segment {
+  pub fn plain(language: Option<String>, text: String) -> Self {
+    Segment {
+      language,
+      parts: vec![Part::Text(text)]
+    }
+  }
+
+  /// Render this segment as markup understood by `espeak -m`.
+  pub fn to_espeak_markup(&self) -> String {
+    let mut markup = String::from("<speak>");
+    for part in self.parts.iter() {
+      match part {
+        Part::Text(text) => markup += &escape(text),
+        Part::Emphasis(level, text) => {
+          markup += &format!(
+            "<emphasis level=\"{}\">{}</emphasis>",
+            escape(level),
+            escape(text)
+          )
+        }
+        Part::Break(duration) => {
+          markup += &format!("<break time=\"{}ms\"/>", duration.as_millis())
+        }
+      }
+    }
+    markup += "</speak>";
+    markup
+  }
+
+  fn push(&mut self, part: Part) {
+    match (self.parts.last_mut(), part) {
+      (Some(Part::Text(text)), Part::Text(more)) => text.push_str(&more),
+      (Some(Part::Break(duration)), Part::Break(more)) => *duration += more,
+      (_, part) => self.parts.push(part)
+    }
+  }
+
+  fn is_silent(&self) -> bool {
+    self.parts.iter().all(|part| match part {
+      Part::Text(text) | Part::Emphasis(_, text) => text.trim().is_empty(),
+      Part::Break(_) => true
+    })
+  }
+}
+
+/// Does this text look like an SSML document?
+pub fn is_ssml(text: &str) -> bool {
+  let text = text.trim_start();
+  let text = match text.strip_prefix("<?xml") {
+    Some(rest) => rest.split_once("?>").map_or("", |(_, rest)| rest).trim_start(),
+    None => text
+  };
+  text.starts_with("<speak")
+}
+
+/// Parse an SSML document into segments.
+///
+/// `language` is used for text which is not covered by any `xml:lang`
+/// attribute.
+pub fn parse(document: &str, language: Option<&str>) -> Result<Vec<Segment>> {
+  let document =
+    Document::parse(document).chain_err(|| "Failed to parse SSML document")?;
+  let root = document.root_element();
+  if root.tag_name().name() != "speak" {
+    return Err("SSML document must have a <speak> root element".into());
+  }
+  let mut segments = vec![Segment {
+    language: language.map(str::to_string),
+    parts: Vec::new()
+  }];
+  walk(root, language, &mut segments)?;
+  Ok(
+    segments
+      .into_iter()
+      .filter(|segment| !segment.is_silent())
+      .collect()
+  )
+}
+
+fn walk(
+  node: Node,
+  language: Option<&str>,
+  segments: &mut Vec<Segment>
+) -> Result<()> {
+  let language = node.attribute((XML_NAMESPACE, "lang")).or(language);
+  if segments.last().unwrap().language.as_deref() != language {
+    segments.push(Segment {
+      language: language.map(str::to_string),
+      parts: Vec::new()
+    });
+  }
+  for child in node.children() {
+    if child.is_text() {
+      let text = child.text().unwrap_or_default();
+      segments.last_mut().unwrap().push(Part::Text(text.to_string()));
+    } else if child.is_element() {
+      match child.tag_name().name() {
+        "break" => {
+          let duration = match (child.attribute("time"), child.attribute("strength")) {
+            (Some(time), _) => {
+              parse_duration(time).chain_err(|| "Invalid break time in SSML")?
+            }
+            (None, strength) => break_strength(strength.unwrap_or("medium"))?
+          };
+          segments.last_mut().unwrap().push(Part::Break(duration));
+        }
+        "emphasis" => {
+          let text = child
+            .descendants()
+            .filter(|node| node.is_text())
+            .filter_map(|node| node.text())
+            .collect::<String>();
+          let level = child.attribute("level").unwrap_or("moderate");
+          segments
+            .last_mut()
+            .unwrap()
+            .push(Part::Emphasis(level.to_string(), text));
+        }
+        "audio" | "mark" | "desc" => {}
+        name => {
+          walk(child, language, segments)?;
+          if segments.last().unwrap().language.as_deref() != language {
+            segments.push(Segment {
+              language: language.map(str::to_string),
+              parts: Vec::new()
+            });
+          }
+          let pause = match name {
+            "p" => Some("strong"),
+            "s" => Some("medium"),
+            _ => None
+          };
+          if let Some(strength) = pause {
+            segments
+              .last_mut()
+              .unwrap()
+              .push(Part::Break(break_strength(strength)?));
+          }
+        }
+      }
+    }
+  }
+  Ok(())
+}
+
+fn break_strength(strength: &str) -> Result<Duration> {
+  let millis = match strength {
+    "none" => 0,
+    "x-weak" => 100,
+    "weak" => 250,
+    "medium" => 400,
+    "strong" => 750,
+    "x-strong" => 1200,
+    _ => return Err(format!("Unknown break strength '{}'", strength).into())
+  };
+  Ok(Duration::from_millis(millis))
+}
+
+fn escape(text: &str) -> String {
+  text
+    .replace('&', "&amp;")
+    .replace('<', "&lt;")
+    .replace('>', "&gt;")
+    .replace('"', "&quot;")
+}
+
+/// Split segments so that no resulting segment contains more than
+/// `max_chars` characters of text.
+///
+/// Text is preferably split at sentence boundaries, then at whitespace.
+pub fn chunk(segments: Vec<Segment>, max_chars: usize) -> Vec<Segment> {
+  let mut chunks = Vec::new();
+  for segment in segments.into_iter() {
+    let mut current = Segment {
+      language: segment.language.clone(),
+      parts: Vec::new()
+    };
+    let mut length = 0;
+    for part in segment.parts.into_iter() {
+      let pieces = match part {
+        Part::Text(text) => split_text(&text, max_chars)
+          .into_iter()
+          .map(Part::Text)
+          .collect(),
+        part => vec![part]
+      };
+      for piece in pieces.into_iter() {
+        if length > 0 && length + piece.len() > max_chars {
+          chunks.push(current);
+          current = Segment {
+            language: segment.language.clone(),
+            parts: Vec::new()
+          };
+          length = 0;
+        }
+        length += piece.len();
+        current.push(piece);
+      }
+    }
+    if !current.is_silent() {
+      chunks.push(current);
+    }
+  }
+  chunks.retain(|chunk| !chunk.is_silent());
+  chunks
+}
+
+fn split_text(text: &str, max_chars: usize) -> Vec<String> {
+  let mut pieces = Vec::new();
+  let mut rest = text;
+  while rest.chars().count() > max_chars {
+    let limit = rest
+      .char_indices()
+      .nth(max_chars)
+      .map_or(rest.len(), |(index, _)| index);
+    let head = &rest[..limit];
+    let at = sentence_end(head)
+      .or_else(|| head.rfind(char::is_whitespace))
+      .filter(|&at| at > 0)
+      .unwrap_or(limit);
+    pieces.push(rest[..at].to_string());
+    rest = &rest[at..];
+  }
+  if !rest.is_empty() {
+    pieces.push(rest.to_string());
+  }
+  pieces
+}
+
+fn sentence_end(text: &str) -> Option<usize> {
+  text
+    .char_indices()
+    .zip(text.chars().skip(1))
+    .filter(|((_, c), next)| matches!(c, '.' | '!' | '?' | '\n') && next.is_whitespace())
+    .map(|((index, c), _)| index + c.len_utf8())
+    .last()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn text(text: &str) -> Part {
+    Part::Text(text.to_string())
+  }
+
+  fn millis(millis: u64) -> Part {
+    Part::Break(Duration::from_millis(millis))
+  }
+
+  #[test]
+  fn recognizes_documents() {
+    assert!(is_ssml("  <speak>Hallo</speak>"));
+    assert!(is_ssml("<?xml version=\"1.0\"?>\n<speak>Hallo</speak>"));
+    assert!(!is_ssml("Hallo <speak>"));
+  }
+
+  #[test]
+  fn flattens_nested_emphasis() {
+    let segments = parse(
+      "<speak>Time for <emphasis level=\"strong\">very <emphasis>nested</emphasis> \
+       school</emphasis>.</speak>",
+      None
+    )
+    .unwrap();
+    assert_eq!(
+      segments,
+      vec![Segment {
+        language: None,
+        parts: vec![
+          text("Time for "),
+          Part::Emphasis(String::from("strong"), String::from("very nested school")),
+          text(".")
+        ]
+      }]
+    );
+  }
+
+  #[test]
+  fn keeps_breaks_inside_sentences() {
+    let segments =
+      parse("<speak><s>One<break time=\"500ms\"/>two</s><s>three</s></speak>", None).unwrap();
+    assert_eq!(
+      segments[0].parts,
+      vec![text("One"), millis(500), text("two"), millis(400), text("three"), millis(400)]
+    );
+  }
+
+  #[test]
+  fn merges_adjacent_breaks() {
+    let segments =
+      parse("<speak>One<break strength=\"weak\"/><break time=\"1s\"/>two</speak>", None).unwrap();
+    assert_eq!(segments[0].parts, vec![text("One"), millis(1250), text("two")]);
+  }
+
+  #[test]
+  fn switches_language() {
+    let segments = parse(
+      "<speak xml:lang=\"de\">Hallo <lang xml:lang=\"en\">world</lang> Welt</speak>",
+      Some("fr")
+    )
+    .unwrap();
+    let languages: Vec<Option<&str>> =
+      segments.iter().map(|segment| segment.language.as_deref()).collect();
+    assert_eq!(languages, vec![Some("de"), Some("en"), Some("de")]);
+    assert_eq!(segments[1].parts, vec![text("world")]);
+  }
+
+  #[test]
+  fn rejects_invalid_documents() {
+    assert!(parse("<voice>Hallo</voice>", None).is_err());
+    assert!(parse("<speak>Hallo", None).is_err());
+    assert!(parse("<speak><break strength=\"loud\"/></speak>", None).is_err());
+    assert!(parse("<speak><break time=\"soon\"/></speak>", None).is_err());
+  }
+
+  #[test]
+  fn finds_last_sentence_end() {
+    assert_eq!(sentence_end("One. Two! Three"), Some(9));
+    assert_eq!(sentence_end("Pi is 3.14 or so"), None);
+    assert_eq!(sentence_end("No end."), None);
+  }
+
+  #[test]
+  fn splits_text_at_sentences_then_whitespace() {
+    assert_eq!(split_text("One. Two three", 12), vec!["One.", " Two three"]);
+    assert_eq!(split_text("one two three", 9), vec!["one two", " three"]);
+    assert_eq!(split_text("abcdefghij", 4), vec!["abcd", "efgh", "ij"]);
+    assert_eq!(split_text("äöüß", 2), vec!["äö", "üß"]);
+  }
+
+  #[test]
+  fn chunks_respect_max_chars() {
+    let segments = vec![Segment::plain(
+      Some(String::from("en")),
+      String::from("A long sentence without any end which goes on and on")
+    )];
+    let chunks = chunk(segments, 10);
+    assert!(chunks.len() > 1);
+    for chunk in chunks.iter() {
+      assert_eq!(chunk.language.as_deref(), Some("en"));
+      assert!(chunk.parts.iter().map(Part::len).sum::<usize>() <= 10);
+    }
+  }
+
+  #[test]
+  fn chunks_keep_short_segments_whole() {
+    let segment = Segment {
+      language: None,
+      parts: vec![text("Hi"), millis(400), Part::Emphasis(String::from("strong"), String::from("there"))]
+    };
+    assert_eq!(chunk(vec![segment.clone()], 100), vec![segment]);
+  }
+
+  #[test]
+  fn renders_espeak_markup() {
+    let segment = Segment {
+      language: None,
+      parts: vec![text("a < b"), millis(250), Part::Emphasis(String::from("strong"), String::from("&"))]
+    };
+    assert_eq!(
+      segment.to_espeak_markup(),
+      "<speak>a &lt; b<break time=\"250ms\"/><emphasis level=\"strong\">&amp;</emphasis></speak>"
+    );
+  }
+}
@@ -0,0 +1,61 @@
+use crate::cache::{parse_size, ClipCache};
+use crate::{Result, ResultExt};
+use clap::{Command, Arg, ArgAction, ArgMatches};
+use humantime::parse_duration;
+use ronor::Sonos;
+
+pub const NAME: &str = "cache";
+
+pub fn build() -> Command {
+  Command::new(NAME)
+    .about("Manage the cache of synthesized audio clips")
+    .subcommand_required(true)
+    .subcommand(
+      Command::new("prune")
+        .about("Remove old clips from the cache")
+        .arg(
+          Arg::new("MAX_SIZE")
+            .long("max-size")
+            .num_args(1)
+            .value_name("SIZE")
+            .default_value("100M")
+            .help("Remove least recently used clips until the cache fits")
+        )
+        .arg(
+          Arg::new("MAX_AGE")
+            .long("max-age")
+            .num_args(1)
+            .value_name("DURATION")
+            .help("Remove clips which have not been used for this long (example: 30days)")
+        )
+        .arg(
+          Arg::new("ALL")
+            .short('a')
+            .long("all")
+            .action(ArgAction::SetTrue)
+            .conflicts_with_all(["MAX_SIZE", "MAX_AGE"])
+            .help("Remove all clips")
+        )
+    )
+}
+
+pub fn run(_sonos: &mut Sonos, matches: &ArgMatches) -> Result<()> {
+  match matches.subcommand() {
+    Some(("prune", matches)) => {
+      let max_size = if matches.get_flag("ALL") {
+        0
+      } else {
+        parse_size(matches.get_one::<String>("MAX_SIZE").unwrap())?
+      };
+      let max_age = match matches.get_one::<String>("MAX_AGE") {
+        Some(age) => Some(parse_duration(age).chain_err(|| "Failed to parse maximum age")?),
+        None => None
+      };
+      let cache = ClipCache::open(max_size)?;
+      let (clips, bytes) = cache.prune(max_size, max_age)?;
+      println!("Removed {} clips ({} bytes)", clips, bytes);
+      Ok(())
+    }
+    _ => unreachable!()
+  }
+}
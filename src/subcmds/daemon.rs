@@ -0,0 +1,179 @@
+use crate::cache::ClipCache;
+use crate::schedule::{due_minutes, Action, Entry, Schedule};
+use crate::subcmds::speak;
+use crate::{ArgMatchesExt, ErrorKind, Result, ResultExt};
+use chrono::{DateTime, Local, TimeDelta, Timelike};
+use clap::{Command, Arg, ArgMatches};
+use humantime::parse_duration;
+use ronor::{Household, HouseholdSnapshot, PlayModes, Sonos};
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+pub const NAME: &str = "daemon";
+
+pub fn build() -> Command {
+  Command::new(NAME)
+    .about("Run scheduled actions (see 'ronor schedule')")
+    .after_help(
+      "The schedule is re-read every minute, so changes take effect without a restart."
+    )
+    .arg(crate::household_arg())
+    .arg(
+      Arg::new("CATCH_UP")
+        .long("catch-up")
+        .num_args(1)
+        .value_name("DURATION")
+        .default_value("5m")
+        .help("How late missed actions may still run, after a suspend for example")
+    )
+}
+
+pub fn run(sonos: &mut Sonos, matches: &ArgMatches) -> Result<()> {
+  let catch_up = parse_duration(matches.get_one::<String>("CATCH_UP").unwrap())
+    .chain_err(|| "Failed to parse catch-up duration")?;
+  let catch_up = TimeDelta::from_std(catch_up).chain_err(|| "Catch-up duration is too long")?;
+  let path = Schedule::path()?;
+  println!("Running schedule from {}", path.display());
+  let mut last: Option<DateTime<Local>> = None;
+  loop {
+    let now = start_of_minute(Local::now());
+    for minute in due_minutes(last, now, catch_up) {
+      run_due(sonos, matches, &path, &minute);
+    }
+    last = Some(now);
+    thread::sleep(Duration::from_secs(u64::from(60 - Local::now().second().min(59))));
+  }
+}
+
+/// Execute all entries due at `time`.
+fn run_due(sonos: &mut Sonos, matches: &ArgMatches, path: &Path, time: &DateTime<Local>) {
+  let minute = time.format("%Y-%m-%d %H:%M").to_string();
+  match Schedule::load(path) {
+    Ok(schedule) => {
+      let due: Vec<&Entry> = schedule.entries.iter().filter(|entry| entry.is_due(time)).collect();
+      if !due.is_empty() {
+        match matches.household(sonos) {
+          Ok(household) => {
+            for entry in due {
+              if let Err(e) = execute(sonos, &household, &entry.action) {
+                match e.kind() {
+                  ErrorKind::UnknownGroup(_) | ErrorKind::UnknownPlayer(_) => {
+                    println!("{} {}: skipped, {}", minute, entry.name, e)
+                  }
+                  _ => println!("{} {}: failed, {}", minute, entry.name, e)
+                }
+              } else {
+                println!("{} {}: {}", minute, entry.name, entry.action);
+              }
+            }
+          }
+          Err(e) => println!("{} {}", minute, e)
+        }
+      }
+    }
+    Err(e) => println!("{} {}", minute, e)
+  }
+}
+
+fn start_of_minute(time: DateTime<Local>) -> DateTime<Local> {
+  time.with_second(0).and_then(|time| time.with_nanosecond(0)).unwrap_or(time)
+}
+
+pub fn execute(sonos: &mut Sonos, household: &Household, action: &Action) -> Result<()> {
+  let targets = HouseholdSnapshot::from(sonos.get_groups(household)?);
+  match action {
+    Action::LoadFavorite {
+      group,
+      favorite,
+      play,
+      repeat,
+      repeat_one,
+      crossfade,
+      shuffle
+    } => {
+      let group = targets
+        .group_by_name(group)
+        .ok_or_else(|| ErrorKind::UnknownGroup(group.to_string()))?;
+      let favorite = sonos
+        .get_favorites(household)?
+        .items
+        .into_iter()
+        .find(|item| &item.name == favorite)
+        .ok_or_else(|| ErrorKind::UnknownFavorite(favorite.to_string()))?;
+      let play_modes = play_modes(*repeat, *repeat_one, *crossfade, *shuffle);
+      sonos.load_favorite(group, &favorite, *play, play_modes.as_ref())?;
+    }
+    Action::LoadPlaylist {
+      group,
+      playlist,
+      play,
+      repeat,
+      repeat_one,
+      crossfade,
+      shuffle
+    } => {
+      let group = targets
+        .group_by_name(group)
+        .ok_or_else(|| ErrorKind::UnknownGroup(group.to_string()))?;
+      let playlist = sonos
+        .get_playlists(household)?
+        .playlists
+        .into_iter()
+        .find(|item| &item.name == playlist)
+        .ok_or_else(|| ErrorKind::UnknownPlaylist(playlist.to_string()))?;
+      let play_modes = play_modes(*repeat, *repeat_one, *crossfade, *shuffle);
+      sonos.load_playlist(group, &playlist, *play, play_modes.as_ref())?;
+    }
+    Action::SetVolume { group, volume } => {
+      let group = targets
+        .group_by_name(group)
+        .ok_or_else(|| ErrorKind::UnknownGroup(group.to_string()))?;
+      sonos.set_group_volume(group, *volume)?;
+    }
+    Action::Speak {
+      player,
+      text,
+      scrape,
+      language
+    } => {
+      let player = targets
+        .player_by_name(player)
+        .ok_or_else(|| ErrorKind::UnknownPlayer(player.to_string()))?;
+      let (language, text) = match (scrape, text) {
+        (Some(uri), _) => {
+          let (language, text) = speak::scrape(uri)?;
+          (Some(language), text)
+        }
+        (None, Some(text)) => (language.clone(), text.clone()),
+        (None, None) => return Err("Nothing to speak".into())
+      };
+      let cache = ClipCache::open(100 * 1024 * 1024)?;
+      let options = speak::Options {
+        cache: Some(&cache),
+        ..speak::Options::default()
+      };
+      speak::speak(sonos, player, speak::segments(text, language, false)?, &options)?;
+    }
+    Action::Pause { group } => {
+      let group = targets
+        .group_by_name(group)
+        .ok_or_else(|| ErrorKind::UnknownGroup(group.to_string()))?;
+      sonos.pause_or_stop(group)?;
+    }
+  }
+  Ok(())
+}
+
+fn play_modes(repeat: bool, repeat_one: bool, crossfade: bool, shuffle: bool) -> Option<PlayModes> {
+  if repeat || repeat_one || crossfade || shuffle {
+    Some(PlayModes {
+      repeat,
+      repeat_one,
+      crossfade,
+      shuffle
+    })
+  } else {
+    None
+  }
+}
@@ -0,0 +1,79 @@
+use crate::fade::{fade, Curve, Outcome, Target};
+use crate::{ArgMatchesExt, Result, ResultExt};
+use clap::{builder::PossibleValuesParser, Command, Arg, ArgGroup, ArgMatches};
+use humantime::parse_duration;
+use ronor::Sonos;
+
+pub const NAME: &str = "fade";
+
+pub fn build() -> Command {
+  Command::new(NAME)
+    .about("Gradually change the volume of a group or player")
+    .after_help(
+      "The fade stops as soon as somebody else changes the volume."
+    )
+    .arg(crate::household_arg())
+    .arg(
+      Arg::new("TO")
+        .short('t')
+        .long("to")
+        .num_args(1)
+        .value_name("VOLUME")
+        .value_parser(value_parser!(u8).range(0..=100))
+        .required(true)
+        .help("Target volume in percent")
+    )
+    .arg(
+      Arg::new("OVER")
+        .short('o')
+        .long("over")
+        .num_args(1)
+        .value_name("DURATION")
+        .default_value("10s")
+        .help("How long the fade should take (example: 30s)")
+    )
+    .arg(
+      Arg::new("CURVE")
+        .short('c')
+        .long("curve")
+        .num_args(1)
+        .value_parser(PossibleValuesParser::new(Curve::NAMES))
+        .default_value("linear")
+    )
+    .arg(
+      Arg::new("PLAYER")
+        .short('p')
+        .long("player")
+        .num_args(1)
+        .value_name("NAME")
+        .help("Fade a single player instead of a group")
+    )
+    .arg(Arg::new("GROUP").help("Name of the group"))
+    .group(
+      ArgGroup::new("TARGET")
+        .args(["GROUP", "PLAYER"])
+        .required(true)
+    )
+}
+
+pub fn run(sonos: &mut Sonos, matches: &ArgMatches) -> Result<()> {
+  let household = matches.household(sonos)?;
+  let targets = sonos.get_groups(&household)?;
+  let to = *matches.get_one::<u8>("TO").unwrap();
+  let over = parse_duration(matches.get_one::<String>("OVER").unwrap())
+    .chain_err(|| "Failed to parse duration")?;
+  let curve: Curve = matches.get_one::<String>("CURVE").unwrap().parse()?;
+  let target = if matches.contains_id("PLAYER") {
+    Target::Player(matches.player(&targets.players)?)
+  } else {
+    Target::Group(matches.group(&targets.groups)?)
+  };
+  if let Outcome::Interrupted(volume) = fade(sonos, target, to, over, curve)? {
+    println!(
+      "Volume of {} was changed to {}, stopped fading",
+      target.name(),
+      volume
+    );
+  }
+  Ok(())
+}
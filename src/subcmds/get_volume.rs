@@ -22,7 +22,7 @@ pub fn build() -> Command {
         .num_args(1)
         .value_name("NAME")
     )
-    .group(ArgGroup::new("TARGET").args(&["GROUP", "PLAYER"]))
+    .group(ArgGroup::new("TARGET").args(["GROUP", "PLAYER"]))
 }
 
 pub fn run(sonos: &mut Sonos, matches: &ArgMatches) -> Result<()> {
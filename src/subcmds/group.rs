@@ -0,0 +1,135 @@
+use crate::subcmds::modify_group::player_ids;
+use crate::{ArgMatchesExt, Result};
+use clap::{Command, Arg, ArgMatches};
+use ronor::{Household, HouseholdSnapshot, ModifiedGroup, PlayerId, Sonos};
+
+pub const NAME: &str = "group";
+
+pub fn build() -> Command {
+  Command::new(NAME)
+    .about("Create, regroup and dissolve groups")
+    .subcommand_required(true)
+    .subcommand(
+      Command::new("create")
+        .about("Create a new group from the given players")
+        .arg(crate::household_arg())
+        .arg(
+          Arg::new("COORDINATOR")
+            .short('c')
+            .long("coordinator")
+            .num_args(1)
+            .value_name("PLAYER")
+            .help("The player to coordinate the new group")
+        )
+        .arg(
+          Arg::new("PLAYERS")
+            .required(true)
+            .num_args(1..)
+            .value_name("PLAYER")
+            .help("Names of the logical players to group")
+        )
+    )
+    .subcommand(
+      Command::new("set")
+        .about("Replace all members of a group")
+        .arg(crate::household_arg())
+        .arg(Arg::new("GROUP").required(true).help("The name of the group"))
+        .arg(
+          Arg::new("PLAYERS")
+            .required(true)
+            .num_args(1..)
+            .value_name("PLAYER")
+            .help("Names of the logical players which should form the group")
+        )
+    )
+    .subcommand(
+      Command::new("ungroup-all")
+        .about("Make every player the sole member of its own group")
+        .arg(crate::household_arg())
+    )
+    .subcommand(
+      Command::new("isolate")
+        .about("Take a player out of its group")
+        .arg(crate::household_arg())
+        .arg(Arg::new("PLAYER").required(true).help("Name of the player"))
+    )
+}
+
+pub fn run(sonos: &mut Sonos, matches: &ArgMatches) -> Result<()> {
+  match matches.subcommand() {
+    Some(("create", matches)) => {
+      let household = matches.household(sonos)?;
+      let targets = HouseholdSnapshot::from(sonos.get_groups(&household)?);
+      let names = names(matches, "PLAYERS");
+      let players = player_ids(names, &targets)?;
+      let group = match matches.get_one::<String>("COORDINATOR") {
+        Some(name) => {
+          let coordinator = player_ids(vec![name.to_string()], &targets)?[0];
+          let mut members = vec![coordinator];
+          members.extend(players.into_iter().filter(|id| *id != coordinator));
+          create_with_coordinator(sonos, &household, &members)?
+        }
+        None => sonos.create_group(&household, &players)?
+      };
+      print_group(&group);
+    }
+    Some(("set", matches)) => {
+      let household = matches.household(sonos)?;
+      let targets = HouseholdSnapshot::from(sonos.get_groups(&household)?);
+      let group = matches.group(targets.groups())?;
+      let players = player_ids(names(matches, "PLAYERS"), &targets)?;
+      print_group(&sonos.set_group_members(group, &players)?);
+    }
+    Some(("ungroup-all", matches)) => {
+      let household = matches.household(sonos)?;
+      let targets = HouseholdSnapshot::from(sonos.get_groups(&household)?);
+      for group in targets.groups().iter().filter(|group| group.player_ids.len() > 1) {
+        print_group(&sonos.set_group_members(group, &[&group.coordinator_id])?);
+      }
+    }
+    Some(("isolate", matches)) => {
+      let household = matches.household(sonos)?;
+      let targets = HouseholdSnapshot::from(sonos.get_groups(&household)?);
+      let player = matches.player(targets.players())?;
+      let group = targets
+        .group_of(&player.id)
+        .ok_or("Player is not part of any group")?;
+      if group.player_ids.len() == 1 {
+        return Err(format!("{} is already on its own", player.name).into());
+      }
+      print_group(&sonos.create_group(&household, &[&player.id])?);
+    }
+    _ => unreachable!()
+  }
+  Ok(())
+}
+
+/// Create a group whose coordinator is the first of `members`.
+///
+/// The coordinator is split off into its own group first, the others join it.
+pub fn create_with_coordinator(
+  sonos: &mut Sonos,
+  household: &Household,
+  members: &[&PlayerId]
+) -> Result<ModifiedGroup> {
+  let created = sonos.create_group(household, &members[..1])?;
+  if members.len() == 1 {
+    return Ok(created);
+  }
+  let targets = HouseholdSnapshot::from(sonos.get_groups(household)?);
+  let group = targets
+    .group(&created.id)
+    .ok_or("Newly created group disappeared")?;
+  Ok(sonos.set_group_members(group, members)?)
+}
+
+fn names(matches: &ArgMatches, id: &str) -> Vec<String> {
+  matches
+    .get_many::<String>(id)
+    .map(|values| values.cloned().collect())
+    .unwrap_or_default()
+}
+
+pub fn print_group(group: &ModifiedGroup) {
+  println!("{} ({})", group.name, group.id);
+}
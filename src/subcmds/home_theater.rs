@@ -0,0 +1,79 @@
+use crate::{ArgMatchesExt, Result};
+use clap::{builder::PossibleValuesParser, Command, Arg, ArgMatches};
+use ronor::{Capability, Sonos, TvPowerState};
+
+pub const NAME: &str = "home-theater";
+
+pub fn build() -> Command {
+  Command::new(NAME)
+    .about("Show or change home theater options and TV power")
+    .arg(crate::household_arg())
+    .arg(
+      Arg::new("NIGHT_MODE")
+        .long("night-mode")
+        .num_args(1)
+        .value_parser(PossibleValuesParser::new(["on", "off"]))
+        .help("Reduce the volume of loud sounds")
+    )
+    .arg(
+      Arg::new("ENHANCE_DIALOG")
+        .long("enhance-dialog")
+        .num_args(1)
+        .value_parser(PossibleValuesParser::new(["on", "off"]))
+        .help("Make speech easier to understand")
+    )
+    .arg(
+      Arg::new("TV")
+        .long("tv")
+        .num_args(1)
+        .value_parser(PossibleValuesParser::new(["on", "standby"]))
+        .help("Switch the connected TV on or to standby")
+    )
+    .arg(
+      Arg::new("PLAYER")
+        .required(true)
+        .help("Name of the player")
+    )
+}
+
+pub fn run(sonos: &mut Sonos, matches: &ArgMatches) -> Result<()> {
+  let household = matches.household(sonos)?;
+  let targets = sonos.get_groups(&household)?;
+  let player = matches.player(&targets.players)?;
+  let night_mode = matches.get_one::<String>("NIGHT_MODE").map(|value| value == "on");
+  let enhance_dialog = matches.get_one::<String>("ENHANCE_DIALOG").map(|value| value == "on");
+  let tv = matches.get_one::<String>("TV");
+  let options_wanted = tv.is_none() || night_mode.is_some() || enhance_dialog.is_some();
+  if options_wanted && !player.capabilities.contains(&Capability::HtPlayback) {
+    return Err(format!("{} is not a home theater player", player.name).into());
+  }
+  if let Some(tv) = tv {
+    if !player.capabilities.contains(&Capability::HtPowerState) {
+      return Err(format!("{} can not control the power of a TV", player.name).into());
+    }
+    let state = if tv == "on" {
+      TvPowerState::On
+    } else {
+      TvPowerState::Standby
+    };
+    sonos.set_tv_power_state(player, &state)?;
+  }
+  if night_mode.is_none() && enhance_dialog.is_none() {
+    if tv.is_none() {
+      let options = sonos.get_home_theater_options(player)?;
+      let on_off = |option: bool| if option { "on" } else { "off" };
+      println!("night-mode: {}", on_off(options.night_mode));
+      println!("enhance-dialog: {}", on_off(options.enhance_dialog));
+    }
+    return Ok(());
+  }
+  let mut options = sonos.get_home_theater_options(player)?;
+  if let Some(night_mode) = night_mode {
+    options.night_mode = night_mode;
+  }
+  if let Some(enhance_dialog) = enhance_dialog {
+    options.enhance_dialog = enhance_dialog;
+  }
+  sonos.set_home_theater_options(player, &options)?;
+  Ok(())
+}
@@ -1,6 +1,6 @@
 use crate::Result;
 use clap::{Command, Arg, ArgMatches};
-use ronor::{Capability, HouseholdId, Player, PlayerId, Sonos};
+use ronor::{Capability, HouseholdId, HouseholdSnapshot, Sonos};
 
 pub const NAME: &str = "inventory";
 
@@ -61,57 +61,33 @@ pub fn run(sonos: &mut Sonos, matches: &ArgMatches) -> Result<()> {
   for household in sonos.get_households()?.iter().filter(|household| {
     household_id
       .as_ref()
-      .map_or(true, |household_id| household_id == &household.id)
+      .is_none_or(|household_id| household_id == &household.id)
   }) {
     if household_id.is_none() {
       println!("Household: {}", household.id);
     }
-    let targets = sonos.get_groups(household)?;
-    fn find_player<'a>(
-      players: &'a [Player],
-      player_id: &PlayerId
-    ) -> Option<&'a Player> {
-      players.iter().find(|player| &player.id == player_id)
-    }
+    let targets = HouseholdSnapshot::from(sonos.get_groups(household)?);
     if matches.contains_id("PLAYERS")
       || audio_clip.is_some()
       || ht_playback.is_some()
       || line_in.is_some()
     {
+      let required: Vec<&Capability> =
+        [&audio_clip, &ht_playback, &line_in].into_iter().flatten().collect();
       for player in targets
-        .players
+        .players()
         .iter()
-        .filter(|player| {
-          audio_clip
-            .as_ref()
-            .map_or(true, |capability| player.capabilities.contains(capability))
-        })
-        .filter(|player| {
-          ht_playback
-            .as_ref()
-            .map_or(true, |capability| player.capabilities.contains(capability))
-        })
-        .filter(|player| {
-          line_in
-            .as_ref()
-            .map_or(true, |capability| player.capabilities.contains(capability))
-        })
+        .filter(|player| required.iter().all(|c| player.capabilities.contains(c)))
       {
         println!("{}", player.name);
       }
     } else {
-      for group in targets.groups.iter() {
+      for group in targets.groups().iter() {
         print!("{}", group.name);
-        let mut player_ids = group.player_ids.iter();
-        if let Some(player_id) = player_ids.next() {
-          if let Some(player) = find_player(&targets.players, player_id) {
-            print!(" = {}", player.name);
-            for player_id in player_ids {
-              if let Some(player) = find_player(&targets.players, player_id) {
-                print!(" + {}", player.name);
-              }
-            }
-          }
+        let names: Vec<&str> =
+          targets.members(group).map(|player| player.name.as_str()).collect();
+        if !names.is_empty() {
+          print!(" = {}", names.join(" + "));
         }
         println!();
       }
@@ -1,6 +1,9 @@
-use crate::{ArgMatchesExt, Result};
-use clap::{Command, Arg, ArgMatches, builder::PossibleValuesParser};
-use ronor::Sonos;
+use crate::interrupt::PausedPlayback;
+use crate::{ArgMatchesExt, Result, ResultExt};
+use clap::{Command, Arg, ArgAction, ArgMatches, builder::PossibleValuesParser};
+use humantime::parse_duration;
+use ronor::{HouseholdSnapshot, Sonos};
+use std::time::Instant;
 use url::Url;
 
 pub const NAME: &str = "load-audio-clip";
@@ -28,7 +31,7 @@ pub fn build() -> Command {
         .short('t')
         .long("type")
         .num_args(1)
-        .value_parser(PossibleValuesParser::new(&["Chime", "Custom"]))
+        .value_parser(PossibleValuesParser::new(["Chime", "Custom"]))
 	.num_args(1)
     )
     .arg(
@@ -36,7 +39,7 @@ pub fn build() -> Command {
         .short('p')
         .long("priority")
         .num_args(1)
-        .value_parser(PossibleValuesParser::new(&["Low", "High"]))
+        .value_parser(PossibleValuesParser::new(["Low", "High"]))
 	.num_args(1)
     )
     .arg(
@@ -54,6 +57,21 @@ pub fn build() -> Command {
         .value_name("STRING")
         .help("HTTP Authorization string")
     )
+    .arg(
+      Arg::new("INTERRUPT")
+        .long("interrupt")
+        .action(ArgAction::SetTrue)
+        .requires("DURATION")
+        .help("Pause the group instead of ducking and resume it afterwards")
+    )
+    .arg(
+      Arg::new("DURATION")
+        .short('d')
+        .long("duration")
+        .num_args(1)
+        .requires("INTERRUPT")
+        .help("Length of the audio clip, playback resumes after it (example: 5s)")
+    )
     .arg(
       Arg::new("PLAYER")
         .required(true)
@@ -62,36 +80,58 @@ pub fn build() -> Command {
     .arg(
       Arg::new("URL")
         .required(true)
+        .value_parser(value_parser!(Url))
         .help("Location of the audio clip")
     )
 }
 
 pub fn run(sonos: &mut Sonos, matches: &ArgMatches) -> Result<()> {
   let household = matches.household(sonos)?;
-  let targets = sonos.get_groups(&household)?;
-  let player = matches.player(&targets.players)?;
+  let targets = HouseholdSnapshot::from(sonos.get_groups(&household)?);
+  let player = matches.player(targets.players())?;
   let url = matches.get_one::<Url>("URL").unwrap();
   if url.has_host() {
+    let clip_type = match matches.get_one::<String>("CLIP_TYPE") {
+      Some(s) => Some(s.parse()?),
+      None => None
+    };
+    let priority = match matches.get_one::<String>("PRIORITY") {
+      Some(s) => Some(s.parse()?),
+      None => None
+    };
+    let volume = match matches.get_one::<String>("VOLUME") {
+      Some(s) => Some(s.parse()?),
+      None => None
+    };
+    let interrupt = match matches.get_one::<String>("DURATION") {
+      Some(duration) => {
+        let duration = parse_duration(duration).chain_err(|| "Failed to parse duration")?;
+        let group = targets
+          .group_of(&player.id)
+          .ok_or("Player is not part of any group")?;
+        Some((group, duration, PausedPlayback::pause(sonos, group)?))
+      }
+      None => None
+    };
     let http_auth = matches.get_one::<String>("HTTP_AUTHORIZATION");
-    sonos.load_audio_clip(
+    let clip = sonos.load_audio_clip(
       player,
       matches.get_one::<String>("APP_ID").unwrap(),
       matches.get_one::<String>("NAME").unwrap(),
-      match matches.get_one::<String>("CLIP_TYPE") {
-        Some(s) => Some(s.parse()?),
-        None => None
-      },
-      match matches.get_one::<String>("PRIORITY") {
-        Some(s) => Some(s.parse()?),
-        None => None
-      },
-      match matches.get_one::<String>("VOLUME") {
-        Some(s) => Some(s.parse()?),
-        None => None
-      },
+      clip_type,
+      priority,
+      volume,
       http_auth.map(|a| a.as_str()),
       Some(url)
-    )?;
+    );
+    if let Some((group, duration, paused)) = interrupt {
+      let end = match clip {
+        Ok(_) => Instant::now() + duration,
+        Err(_) => Instant::now()
+      };
+      paused.resume_at(sonos, group, end)?;
+    }
+    clip?;
   } else {
     return Err(
       "The URL you provided does not look like Sonos will be able to reach it".into()
@@ -14,7 +14,7 @@ pub fn build() -> Command {
         .long("play")
         .help("Automatically start playback")
     )
-    .args(&crate::play_modes_args())
+    .args(crate::play_modes_args())
     .arg(
       Arg::new("PLAYLIST")
         .required(true)
@@ -1,6 +1,6 @@
 use crate::{ArgMatchesExt, ErrorKind, Result};
 use clap::{Command, Arg, ArgAction, ArgMatches};
-use ronor::{Player, PlayerId, Sonos};
+use ronor::{HouseholdSnapshot, PlayerId, Sonos};
 
 pub const NAME: &str = "modify-group";
 
@@ -37,25 +37,25 @@ pub fn build() -> Command {
 
 pub fn run(sonos: &mut Sonos, matches: &ArgMatches) -> Result<()> {
   let household = matches.household(sonos)?;
-  let targets = sonos.get_groups(&household)?;
-  let group = matches.group(&targets.groups)?;
+  let targets = HouseholdSnapshot::from(sonos.get_groups(&household)?);
+  let group = matches.group(targets.groups())?;
   let add = matches.get_many::<String>("ADD").map(|vals| vals.map(|x| x.to_string()).collect::<Vec<_>>()).unwrap_or_default();
   let remove = matches.get_many::<String>("REMOVE").map(|vals| vals.map(|x| x.to_string()).collect::<Vec<_>>()).unwrap_or_default();
-  let player_ids_to_add = player_ids(add, &targets.players)?;
-  let player_ids_to_remove = player_ids(remove, &targets.players)?;
+  let player_ids_to_add = player_ids(add, &targets)?;
+  let player_ids_to_remove = player_ids(remove, &targets)?;
   let modified_group =
     sonos.modify_group_members(group, &player_ids_to_add, &player_ids_to_remove)?;
   println!("{} -> {}", group.name, modified_group.name);
   Ok(())
 }
 
-fn player_ids<'a>(
+pub fn player_ids(
   names: Vec<String>,
-  players: &'a [Player]
-) -> Result<Vec<&'a PlayerId>> {
+  targets: &HouseholdSnapshot
+) -> Result<Vec<&PlayerId>> {
   let mut ids = Vec::new();
   for name in names.iter() {
-    match players.iter().find(|p| &p.name == name) {
+    match targets.player_by_name(name) {
       None => return Err(ErrorKind::UnknownPlayer(name.to_string()).into()),
       Some(player) => ids.push(&player.id)
     }
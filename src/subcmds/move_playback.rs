@@ -0,0 +1,78 @@
+use crate::{ArgMatchesExt, ErrorKind, Result};
+use clap::{Command, Arg, ArgAction, ArgMatches};
+use ronor::{HouseholdSnapshot, PlaybackState, Sonos};
+use std::thread;
+use std::time::Duration;
+
+pub const NAME: &str = "move";
+
+const NO_GROUP: &str = "Player is not part of any group";
+
+pub fn build() -> Command {
+  Command::new(NAME)
+    .about("Move playback from one player to another")
+    .arg(crate::household_arg())
+    .arg(
+      Arg::new("VOLUME")
+        .short('v')
+        .long("volume")
+        .action(ArgAction::SetTrue)
+        .help("Carry the volume over to the new player")
+    )
+    .arg(
+      Arg::new("FROM_PLAYER")
+        .required(true)
+        .help("Name of the player which is playing now")
+    )
+    .arg(
+      Arg::new("TO_PLAYER")
+        .required(true)
+        .help("Name of the player which should take over")
+    )
+}
+
+pub fn run(sonos: &mut Sonos, matches: &ArgMatches) -> Result<()> {
+  let household = matches.household(sonos)?;
+  let targets = HouseholdSnapshot::from(sonos.get_groups(&household)?);
+  let from_name = matches.get_one::<String>("FROM_PLAYER").unwrap();
+  let from = targets
+    .player_by_name(from_name)
+    .ok_or_else(|| ErrorKind::UnknownPlayer(from_name.to_string()))?;
+  let to_name = matches.get_one::<String>("TO_PLAYER").unwrap();
+  let to = targets
+    .player_by_name(to_name)
+    .ok_or_else(|| ErrorKind::UnknownPlayer(to_name.to_string()))?;
+  if from.id == to.id {
+    return Err("Source and destination are the same player".into());
+  }
+  let group = targets.group_of(&from.id).ok_or(NO_GROUP)?;
+  let was_playing = matches!(
+    group.playback_state,
+    PlaybackState::Playing | PlaybackState::Buffering
+  );
+  if !group.player_ids.contains(&to.id) {
+    sonos.modify_group_members(group, &[&to.id], &[])?;
+  }
+  if matches.get_flag("VOLUME") {
+    let volume = sonos.get_player_volume(from)?;
+    if !volume.fixed {
+      sonos.set_player_volume(to, volume.volume)?;
+    }
+  }
+  // Membership changes can alter the group ID, so look it up again.
+  let targets = HouseholdSnapshot::from(sonos.get_groups(&household)?);
+  let group = targets.group_of(&from.id).ok_or(NO_GROUP)?;
+  sonos.modify_group_members(group, &[], &[&from.id])?;
+  if group.coordinator_id == from.id && was_playing {
+    // Another member takes over coordination, which might interrupt playback.
+    thread::sleep(Duration::from_secs(1));
+    let targets = HouseholdSnapshot::from(sonos.get_groups(&household)?);
+    let group = targets.group_of(&to.id).ok_or(NO_GROUP)?;
+    if group.playback_state != PlaybackState::Playing {
+      sonos.play(group)?;
+    }
+  }
+  let targets = HouseholdSnapshot::from(sonos.get_groups(&household)?);
+  println!("{} -> {}", from.name, targets.group_of(&to.id).ok_or(NO_GROUP)?.name);
+  Ok(())
+}
@@ -0,0 +1,53 @@
+use crate::{ArgMatchesExt, Result, ResultExt};
+use clap::{Command, Arg, ArgMatches};
+use humantime::parse_duration;
+use ronor::Sonos;
+
+pub const NAME: &str = "mpd";
+
+pub fn build() -> Command {
+  Command::new(NAME)
+    .about("Let MPD clients control the group of a player")
+    .after_help(
+      "Clients like mpc, ncmpcpp or M.A.L.P. see what the group plays as \
+       the only song in the queue, and favorites and Sonos playlists as \
+       stored playlists.  There is no music database.  Runs until interrupted."
+    )
+    .arg(crate::household_arg())
+    .arg(
+      Arg::new("LISTEN")
+        .short('l')
+        .long("listen")
+        .num_args(1)
+        .value_name("HOST:PORT")
+        .default_value("127.0.0.1:6600")
+        .help("Where to accept connections")
+    )
+    .arg(
+      Arg::new("INTERVAL")
+        .short('i')
+        .long("interval")
+        .num_args(1)
+        .value_name("DURATION")
+        .default_value("2s")
+        .help("How often to check for changes")
+    )
+    .arg(
+      Arg::new("PLAYER")
+        .required(true)
+        .help("The player whose group to control")
+    )
+}
+
+pub fn run(sonos: &mut Sonos, matches: &ArgMatches) -> Result<()> {
+  let household = matches.household(sonos)?;
+  let interval = parse_duration(matches.get_one::<String>("INTERVAL").unwrap())
+    .chain_err(|| "Failed to parse interval")?;
+  crate::mpd::serve(
+    sonos,
+    &household,
+    matches.get_one::<String>("PLAYER").unwrap(),
+    matches.get_one::<String>("LISTEN").unwrap(),
+    interval
+  )
+}
@@ -0,0 +1,32 @@
+use crate::{ArgMatchesExt, Result, ResultExt};
+use clap::{Command, Arg, ArgMatches};
+use humantime::parse_duration;
+use ronor::Sonos;
+
+pub const NAME: &str = "mpris";
+
+pub fn build() -> Command {
+  Command::new(NAME)
+    .about("Expose all groups as MPRIS media players on the D-Bus session bus")
+    .after_help(
+      "Media keys, desktop widgets and tools like playerctl can then control \
+       playback.  Runs until interrupted."
+    )
+    .arg(crate::household_arg())
+    .arg(
+      Arg::new("INTERVAL")
+        .short('i')
+        .long("interval")
+        .num_args(1)
+        .value_name("DURATION")
+        .default_value("2s")
+        .help("How often to check for changes")
+    )
+}
+
+pub fn run(sonos: &mut Sonos, matches: &ArgMatches) -> Result<()> {
+  let household = matches.household(sonos)?;
+  let interval = parse_duration(matches.get_one::<String>("INTERVAL").unwrap())
+    .chain_err(|| "Failed to parse interval")?;
+  crate::mpris::serve(sonos, &household, interval)
+}
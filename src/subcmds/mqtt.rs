@@ -0,0 +1,117 @@
+use crate::mqtt::{serve, Options};
+use crate::{ArgMatchesExt, Result, ResultExt};
+use clap::{Command, Arg, ArgAction, ArgMatches};
+use humantime::parse_duration;
+use ronor::Sonos;
+use rumqttc::MqttOptions;
+use std::fs::read_to_string;
+use std::time::Duration;
+
+pub const NAME: &str = "mqtt";
+
+/// Environment variable holding the password for the broker.
+const PASSWORD: &str = "RONOR_MQTT_PASSWORD";
+
+pub fn build() -> Command {
+  Command::new(NAME)
+    .about("Publish group and player state to an MQTT broker and accept commands")
+    .after_help(
+      "State is published below PREFIX/group/<coordinator>/ and \
+       PREFIX/player/<player>/.  Publish to a state, volume or muted topic \
+       with /set appended to control playback.  Runs until interrupted.\n\n\
+       The password for the broker is read from --password-file, or from \
+       the RONOR_MQTT_PASSWORD environment variable."
+    )
+    .arg(crate::household_arg())
+    .arg(
+      Arg::new("BROKER")
+        .short('b')
+        .long("broker")
+        .num_args(1)
+        .value_name("HOST[:PORT]")
+        .default_value("localhost")
+        .help("The MQTT broker to connect to")
+    )
+    .arg(
+      Arg::new("USERNAME")
+        .short('u')
+        .long("username")
+        .num_args(1)
+        .help("User name for the broker")
+    )
+    .arg(
+      Arg::new("PASSWORD_FILE")
+        .long("password-file")
+        .num_args(1)
+        .value_name("FILE")
+        .requires("USERNAME")
+        .help("Read the password for the broker from FILE")
+    )
+    .arg(
+      Arg::new("PREFIX")
+        .long("prefix")
+        .num_args(1)
+        .default_value("ronor")
+        .help("Prefix of all topics")
+    )
+    .arg(
+      Arg::new("DISCOVERY_PREFIX")
+        .long("discovery-prefix")
+        .num_args(1)
+        .default_value("homeassistant")
+        .help("Where Home Assistant expects discovery messages")
+    )
+    .arg(
+      Arg::new("NO_DISCOVERY")
+        .long("no-discovery")
+        .action(ArgAction::SetTrue)
+        .help("Do not publish Home Assistant discovery messages")
+    )
+    .arg(
+      Arg::new("INTERVAL")
+        .short('i')
+        .long("interval")
+        .num_args(1)
+        .value_name("DURATION")
+        .default_value("2s")
+        .help("How often to check for changes")
+    )
+}
+
+pub fn run(sonos: &mut Sonos, matches: &ArgMatches) -> Result<()> {
+  let household = matches.household(sonos)?;
+  let broker = matches.get_one::<String>("BROKER").unwrap();
+  let (host, port) = match broker.rsplit_once(':') {
+    Some((host, port)) => (
+      host,
+      port
+        .parse::<u16>()
+        .chain_err(|| format!("Invalid port in '{}'", broker))?
+    ),
+    None => (broker.as_str(), 1883)
+  };
+  let mut mqtt = MqttOptions::new(format!("ronor-{}", std::process::id()), host, port);
+  mqtt.set_keep_alive(Duration::from_secs(30));
+  if let Some(username) = matches.get_one::<String>("USERNAME") {
+    let password = match matches.get_one::<String>("PASSWORD_FILE") {
+      Some(path) => read_to_string(path)
+        .chain_err(|| format!("Failed to read password from '{}'", path))?
+        .trim_end_matches(['\r', '\n'])
+        .to_string(),
+      None => std::env::var(PASSWORD)
+        .chain_err(|| format!("--username needs --password-file or {}", PASSWORD))?
+    };
+    mqtt.set_credentials(username, password);
+  }
+  let options = Options {
+    prefix: matches.get_one::<String>("PREFIX").unwrap().to_string(),
+    discovery_prefix: if matches.get_flag("NO_DISCOVERY") {
+      None
+    } else {
+      matches.get_one::<String>("DISCOVERY_PREFIX").cloned()
+    },
+    interval: parse_duration(matches.get_one::<String>("INTERVAL").unwrap())
+      .chain_err(|| "Failed to parse interval")?
+  };
+  serve(sonos, &household, mqtt, &options)
+}
@@ -1,75 +1,203 @@
-use crate::{ErrorKind, Result};
-use clap::{Command, Arg, ArgMatches};
-use ronor::{PlaybackState, Sonos};
+use crate::now_playing::{fields, FIELDS};
+use crate::ssml::Segment;
+use crate::subcmds::speak::{say, AMPLITUDE, WORDS_PER_MINUTE};
+use crate::{ErrorKind, Result, ResultExt};
+use clap::{Command, Arg, ArgAction, ArgMatches};
+use humantime::parse_duration;
+use ronor::{GroupId, PlaybackState, Sonos};
+use std::collections::HashMap;
+use std::thread;
+use std::time::{Duration, Instant};
 
 pub const NAME: &str = "now-playing";
 
+/// Fields which make up a change worth announcing in `--follow` mode.
+const FOLLOWED: [&str; 6] = ["state", "container", "item", "track", "artist", "stream"];
+
+/// How long a group has to stay buffering before that is announced.
+const BUFFERING_GRACE: Duration = Duration::from_secs(5);
+
 pub fn build() -> Command {
   Command::new(NAME)
     .visible_alias("np")
     .about("Describes what is currently playing")
+    .after_help(format!(
+      "Templates refer to fields in braces, like '{{artist}} - {{track}}'.  \
+       Available fields: {}.",
+      FIELDS.join(", ")
+    ))
+    .arg(
+      Arg::new("FORMAT")
+        .short('f')
+        .long("format")
+        .num_args(1)
+        .value_name("TEMPLATE")
+        .help("Describe each group according to a template")
+    )
+    .arg(
+      Arg::new("FOLLOW")
+        .long("follow")
+        .action(ArgAction::SetTrue)
+        .help("Keep running and print a line whenever something changes")
+    )
+    .arg(
+      Arg::new("INTERVAL")
+        .short('i')
+        .long("interval")
+        .num_args(1)
+        .value_name("DURATION")
+        .default_value("2s")
+        .requires("FOLLOW")
+        .help("How often to check for changes")
+    )
+    .arg(
+      Arg::new("SPEAK")
+        .long("speak")
+        .action(ArgAction::SetTrue)
+        .requires("FOLLOW")
+        .help("Also speak changes through the local sound card with espeak")
+    )
+    .arg(
+      Arg::new("LANGUAGE")
+        .short('l')
+        .long("language")
+        .num_args(1)
+        .requires("SPEAK")
+        .help("What language changes are spoken in")
+    )
     .arg(Arg::new("GROUP"))
 }
 
 pub fn run(sonos: &mut Sonos, matches: &ArgMatches) -> Result<()> {
   let group_name = matches.get_one::<String>("GROUP");
+  let format = matches.get_one::<String>("FORMAT");
+  if let Some(format) = format {
+    render(format, &HashMap::new())?;
+  }
+  if matches.get_flag("FOLLOW") {
+    let interval = parse_duration(matches.get_one::<String>("INTERVAL").unwrap())
+      .chain_err(|| "Failed to parse interval")?;
+    let language = matches.get_one::<String>("LANGUAGE");
+    let speech = matches.get_flag("SPEAK").then(|| language.cloned());
+    return follow(sonos, group_name, format, interval, speech);
+  }
   let mut found = false;
   for household in sonos.get_households()?.iter() {
     for group in sonos
       .get_groups(household)?
       .groups
       .iter()
-      .filter(|group| group_name.map_or(true, |name| name == &group.name))
+      .filter(|group| group_name.is_none_or(|name| name == &group.name))
     {
       found = true;
-      if group.playback_state == PlaybackState::Playing {
-        let metadata_status = sonos.get_metadata_status(group)?;
-        let mut parts = Vec::new();
-        if let Some(container) = &metadata_status.container {
-          if container.type_.is_some()
-            && container.type_.as_ref().unwrap() == "linein.homeTheater"
-          {
-            parts.push("Home theater");
-          } else {
-            if let Some(name) = &container.name {
-              parts.push(name.as_str());
-            }
-            if let Some(service) = &container.service {
-              parts.push(service.name.as_str());
-            }
+      if group.playback_state == PlaybackState::Idle {
+        continue;
+      }
+      let playback_status = sonos.get_playback_status(group)?;
+      let metadata_status = sonos.get_metadata_status(group)?;
+      let fields = fields(group, &playback_status, &metadata_status);
+      match format {
+        Some(format) => println!("{}", render(format, &fields)?),
+        None => {
+          if let Some(description) = describe(&fields) {
+            println!("{}", description);
           }
         }
-        if let Some(current_item) = &metadata_status.current_item {
-          if let Some(name) = &current_item.track.name {
-            parts.push(name.as_str());
-            if let Some(album) = &current_item.track.album {
-              parts.push(album.name.as_str());
-            }
-            if let Some(artist) = &current_item.track.artist {
-              parts.push(artist.name.as_str());
-            }
-            if let Some(author) = &current_item.track.author {
-              parts.push(author.name.as_str());
-            }
-            if let Some(narrator) = &current_item.track.narrator {
-              parts.push(narrator.name.as_str());
-            }
-            if let Some(service) = &current_item.track.service {
-              parts.push(service.name.as_str());
-            }
+      }
+    }
+  }
+  if !found {
+    if let Some(group_name) = group_name {
+      return Err(ErrorKind::UnknownGroup(group_name.to_string()).into());
+    }
+    return Err("No groups found".into());
+  }
+  Ok(())
+}
+
+/// Print (and optionally speak) a line whenever the track, container or
+/// playback state of a group changes.
+fn follow(
+  sonos: &mut Sonos,
+  group_name: Option<&String>,
+  format: Option<&String>,
+  interval: Duration,
+  speech: Option<Option<String>>
+) -> Result<()> {
+  let mut announced: HashMap<GroupId, Vec<Option<String>>> = HashMap::new();
+  let mut buffering: HashMap<GroupId, Instant> = HashMap::new();
+  let mut first = true;
+  loop {
+    let changes = changes(sonos, group_name, &mut announced, &mut buffering);
+    match changes {
+      Ok(changes) => {
+        for fields in changes {
+          let line = match format {
+            Some(format) => render(format, &fields)?,
+            None => summarize(&fields)
+          };
+          println!("{}", line);
+          if let Some(language) = &speech {
+            say(&Segment::plain(language.clone(), line), WORDS_PER_MINUTE, AMPLITUDE)?;
           }
         }
-        if let Some(stream_info) = &metadata_status.stream_info {
-          parts.push(stream_info.trim().trim_matches('-').trim());
-        }
-        let mut parts = parts.iter();
-        if let Some(part) = parts.next() {
-          print!("{} => {}", group.name, part);
-          for part in parts {
-            print!(" - {}", part);
-          }
-          println!();
+      }
+      // Once running, a hiccup should not end the session.
+      Err(e) if !first => eprintln!("{}", e),
+      Err(e) => return Err(e)
+    }
+    first = false;
+    thread::sleep(interval);
+  }
+}
+
+/// Fields of all groups which changed since they were last announced.
+///
+/// Buffering is only reported once it lasts longer than [`BUFFERING_GRACE`],
+/// and groups which are idle from the start are not reported at all.
+fn changes(
+  sonos: &mut Sonos,
+  group_name: Option<&String>,
+  announced: &mut HashMap<GroupId, Vec<Option<String>>>,
+  buffering: &mut HashMap<GroupId, Instant>
+) -> Result<Vec<HashMap<&'static str, String>>> {
+  let mut changes = Vec::new();
+  let mut found = false;
+  for household in sonos.get_households()?.iter() {
+    for group in sonos
+      .get_groups(household)?
+      .groups
+      .iter()
+      .filter(|group| group_name.is_none_or(|name| name == &group.name))
+    {
+      found = true;
+      let fields = if group.playback_state == PlaybackState::Idle {
+        let mut fields = HashMap::new();
+        fields.insert("group", group.name.clone());
+        fields.insert("state", String::from("idle"));
+        fields
+      } else {
+        let playback_status = sonos.get_playback_status(group)?;
+        let metadata_status = sonos.get_metadata_status(group)?;
+        fields(group, &playback_status, &metadata_status)
+      };
+      if fields["state"] == "buffering" {
+        let since = *buffering.entry(group.id.clone()).or_insert_with(Instant::now);
+        if since.elapsed() < BUFFERING_GRACE {
+          continue;
         }
+      } else {
+        buffering.remove(&group.id);
+      }
+      let key: Vec<Option<String>> =
+        FOLLOWED.iter().map(|field| fields.get(field).cloned()).collect();
+      let previous = announced.insert(group.id.clone(), key.clone());
+      let changed = match previous {
+        Some(previous) => previous != key,
+        None => fields["state"] != "idle"
+      };
+      if changed {
+        changes.push(fields);
       }
     }
   }
@@ -79,5 +207,92 @@ pub fn run(sonos: &mut Sonos, matches: &ArgMatches) -> Result<()> {
     }
     return Err("No groups found".into());
   }
-  Ok(())
+  Ok(changes)
+}
+
+/// A single line describing a group, without positions or quality.
+pub fn summarize(fields: &HashMap<&'static str, String>) -> String {
+  let mut parts: Vec<&str> = ["track", "artist", "stream"]
+    .iter()
+    .filter_map(|field| fields.get(field).map(String::as_str))
+    .filter(|part| !part.is_empty())
+    .collect();
+  if parts.is_empty() {
+    parts.extend(fields.get("container").map(String::as_str));
+  }
+  let mut line = fields["group"].clone();
+  if !parts.is_empty() {
+    line += &format!(": {}", parts.join(" - "));
+  }
+  if fields["state"] != "playing" {
+    line += &format!(" ({})", fields["state"]);
+  }
+  line
+}
+
+/// The default description of a group, `None` if nothing is known.
+pub fn describe(fields: &HashMap<&'static str, String>) -> Option<String> {
+  let parts: Vec<&str> = [
+    "container", "track", "album", "artist", "author", "narrator", "service", "stream"
+  ]
+  .iter()
+  .filter_map(|field| fields.get(field).map(String::as_str))
+  .filter(|part| !part.is_empty())
+  .collect();
+  if parts.is_empty() {
+    return None;
+  }
+  let mut description = format!("{} => {}", fields["group"], parts.join(" - "));
+  if let (Some(position), Some(duration)) = (fields.get("position"), fields.get("duration")) {
+    description += &format!(" [{}/{}]", position, duration);
+  }
+  if let Some(quality) = fields.get("quality") {
+    description += &format!(" [{}]", quality);
+  }
+  if fields.contains_key("explicit") {
+    description += " [explicit]";
+  }
+  if fields["state"] != "playing" {
+    description += &format!(" ({})", fields["state"]);
+  }
+  if let Some(next) = fields.get("next") {
+    description += &format!("\n  next: {}", next);
+  }
+  Some(description)
+}
+
+/// Replace `{field}` in `template`, `{{` and `}}` stand for literal braces.
+pub fn render(template: &str, fields: &HashMap<&'static str, String>) -> Result<String> {
+  let mut output = String::new();
+  let mut chars = template.chars().peekable();
+  while let Some(c) = chars.next() {
+    match c {
+      '{' if chars.peek() == Some(&'{') => {
+        chars.next();
+        output.push('{');
+      }
+      '}' if chars.peek() == Some(&'}') => {
+        chars.next();
+        output.push('}');
+      }
+      '{' => {
+        let mut name = String::new();
+        loop {
+          match chars.next() {
+            Some('}') => break,
+            Some(c) => name.push(c),
+            None => return Err("Unterminated field in template".into())
+          }
+        }
+        if !FIELDS.contains(&name.as_str()) {
+          return Err(format!("Unknown field '{}' in template", name).into());
+        }
+        if let Some(value) = fields.get(name.as_str()) {
+          output += value;
+        }
+      }
+      c => output.push(c)
+    }
+  }
+  Ok(output)
 }
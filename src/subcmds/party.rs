@@ -0,0 +1,104 @@
+use crate::subcmds::group::{create_with_coordinator, print_group};
+use crate::{ArgMatchesExt, ErrorKind, Result};
+use clap::{Command, Arg, ArgAction, ArgMatches};
+use ronor::{Capability, HouseholdSnapshot, PlaybackState, PlayerId, Sonos};
+
+pub const NAME: &str = "party";
+
+pub fn build() -> Command {
+  Command::new(NAME)
+    .about("Join all players of a household into one group")
+    .after_help(
+      "Unless --coordinator is given, a player which is currently playing \
+       coordinates the party, so the music keeps going."
+    )
+    .arg(crate::household_arg())
+    .arg(
+      Arg::new("COORDINATOR")
+        .short('c')
+        .long("coordinator")
+        .num_args(1)
+        .value_name("PLAYER")
+        .help("The player to coordinate the party")
+    )
+    .arg(
+      Arg::new("EXCLUDE")
+        .short('x')
+        .long("exclude")
+        .num_args(1..)
+        .action(ArgAction::Append)
+        .value_name("PLAYER")
+        .help("Names of the logical players to leave out")
+    )
+    .arg(
+      Arg::new("EQUALIZE")
+        .short('e')
+        .long("equalize")
+        .num_args(0..=1)
+        .value_name("VOLUME")
+        .value_parser(value_parser!(u8).range(0..=100))
+        .help("Set all players to the same volume, that of the coordinator by default")
+    )
+}
+
+pub fn run(sonos: &mut Sonos, matches: &ArgMatches) -> Result<()> {
+  let household = matches.household(sonos)?;
+  let targets = HouseholdSnapshot::from(sonos.get_groups(&household)?);
+  let exclude: Vec<&String> = matches
+    .get_many::<String>("EXCLUDE")
+    .map(|names| names.collect())
+    .unwrap_or_default();
+  for name in exclude.iter() {
+    if targets.player_by_name(name).is_none() {
+      return Err(ErrorKind::UnknownPlayer(name.to_string()).into());
+    }
+  }
+  let players: Vec<&PlayerId> = targets
+    .with_capability(Capability::Playback)
+    .filter(|player| !exclude.contains(&&player.name))
+    .map(|player| &player.id)
+    .collect();
+  let coordinator = match matches.get_one::<String>("COORDINATOR") {
+    Some(name) => {
+      let player = targets
+        .player_by_name(name)
+        .ok_or_else(|| ErrorKind::UnknownPlayer(name.to_string()))?;
+      if !players.contains(&&player.id) {
+        return Err(format!("{} can not coordinate the party", name).into());
+      }
+      &player.id
+    }
+    None => targets
+      .groups()
+      .iter()
+      .filter(|group| players.contains(&&group.coordinator_id))
+      .max_by_key(|group| group.playback_state == PlaybackState::Playing)
+      .map(|group| &group.coordinator_id)
+      .or_else(|| players.first().copied())
+      .ok_or("No players left to party")?
+  };
+  let mut members = vec![coordinator];
+  members.extend(players.iter().filter(|id| **id != coordinator));
+  let party = match targets.coordinated_by(coordinator) {
+    Some(group) => sonos.set_group_members(group, &members)?,
+    None => create_with_coordinator(sonos, &household, &members)?
+  };
+  print_group(&party);
+  if matches.contains_id("EQUALIZE") {
+    let volume = match matches.get_one::<u8>("EQUALIZE") {
+      Some(volume) => *volume,
+      None => {
+        let coordinator = targets
+          .player(&party.coordinator_id)
+          .ok_or("Coordinator not found")?;
+        sonos.get_player_volume(coordinator)?.volume
+      }
+    };
+    for player in party.player_ids.iter().filter_map(|id| targets.player(id)) {
+      if !sonos.get_player_volume(player)?.fixed {
+        sonos.set_player_volume(player, volume)?;
+      }
+    }
+  }
+  Ok(())
+}
@@ -16,9 +16,9 @@ pub fn run(sonos: &mut Sonos, matches: &ArgMatches) -> Result<()> {
   let household = matches.household(sonos)?;
   let mut found = false;
   for group in sonos.get_groups(&household)?.groups.iter() {
-    if group_name.map_or(true, |name| name == &group.name) {
+    if group_name.is_none_or(|name| name == &group.name) {
       found = true;
-      sonos.pause(group)?;
+      sonos.pause_or_stop(group)?;
     }
   }
   if !found {
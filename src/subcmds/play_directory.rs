@@ -0,0 +1,66 @@
+use crate::cloud_queue::CloudQueue;
+use crate::{ArgMatchesExt, Result};
+use clap::{Command, Arg, ArgAction, ArgMatches};
+use ronor::Sonos;
+use std::path::Path;
+use std::thread;
+
+pub const NAME: &str = "play-directory";
+
+pub fn build() -> Command {
+  Command::new(NAME)
+    .about("Queue the audio files of a local directory on a group")
+    .after_help(
+      "The files are served by a built-in cloud queue, which has to keep \
+       running for as long as the group plays them."
+    )
+    .arg(crate::household_arg())
+    .arg(
+      Arg::new("SERVE")
+        .long("serve")
+        .required(true)
+        .num_args(1)
+        .value_name("HOST:PORT")
+        .help("Address to serve the queue on, has to be reachable by the players")
+    )
+    .arg(
+      Arg::new("NO_PLAY")
+        .long("no-play")
+        .action(ArgAction::SetTrue)
+        .help("Do not start playback")
+    )
+    .arg(
+      Arg::new("GROUP")
+        .required(true)
+        .help("Name of the group")
+    )
+    .arg(
+      Arg::new("DIRECTORY")
+        .required(true)
+        .help("Directory with audio files (mp3, flac, m4a, aac, ogg or wav)")
+    )
+}
+
+pub fn run(sonos: &mut Sonos, matches: &ArgMatches) -> Result<()> {
+  let household = matches.household(sonos)?;
+  let targets = sonos.get_groups(&household)?;
+  let group = matches.group(&targets.groups)?;
+  let queue =
+    CloudQueue::from_directory(Path::new(matches.get_one::<String>("DIRECTORY").unwrap()))?;
+  let (count, version) = (queue.len(), queue.version().to_string());
+  let base = queue.start(matches.get_one::<String>("SERVE").unwrap())?;
+  let session = sonos.join_or_create_session(group, "guru.blind", "ronor", None)?;
+  sonos.load_cloud_queue(
+    &session,
+    &base,
+    None,
+    None,
+    Some(&version),
+    None,
+    !matches.get_flag("NO_PLAY")
+  )?;
+  println!("Serving {} tracks on {}, press Ctrl-C to stop", count, base);
+  loop {
+    thread::park();
+  }
+}
@@ -0,0 +1,60 @@
+use crate::{ArgMatchesExt, Result};
+use clap::{builder::PossibleValuesParser, Command, Arg, ArgMatches};
+use ronor::Sonos;
+
+pub const NAME: &str = "play-mode";
+
+pub fn build() -> Command {
+  Command::new(NAME)
+    .about("Show or change shuffle, repeat and crossfade of what is playing")
+    .arg(crate::household_arg())
+    .args(
+      [
+        ("SHUFFLE", "shuffle", "Shuffle the tracks"),
+        ("REPEAT", "repeat", "Repeat all tracks"),
+        ("REPEAT_ONE", "repeat-one", "Repeat the current track"),
+        ("CROSSFADE", "crossfade", "Do crossfade between tracks")
+      ]
+      .map(|(id, long, help)| {
+        Arg::new(id)
+          .long(long)
+          .num_args(1)
+          .value_parser(PossibleValuesParser::new(["on", "off"]))
+          .help(help)
+      })
+    )
+    .arg(
+      Arg::new("GROUP")
+        .required(true)
+        .help("Name of the group")
+    )
+}
+
+pub fn run(sonos: &mut Sonos, matches: &ArgMatches) -> Result<()> {
+  let household = matches.household(sonos)?;
+  let targets = sonos.get_groups(&household)?;
+  let group = matches.group(&targets.groups)?;
+  let mut play_modes = sonos.get_playback_status(group)?.play_modes;
+  let mut changed = false;
+  for (id, mode) in [
+    ("SHUFFLE", &mut play_modes.shuffle),
+    ("REPEAT", &mut play_modes.repeat),
+    ("REPEAT_ONE", &mut play_modes.repeat_one),
+    ("CROSSFADE", &mut play_modes.crossfade)
+  ] {
+    if let Some(value) = matches.get_one::<String>(id) {
+      *mode = value == "on";
+      changed = true;
+    }
+  }
+  if changed {
+    sonos.set_play_modes(group, &play_modes)?;
+  } else {
+    let on_off = |mode: bool| if mode { "on" } else { "off" };
+    println!("shuffle: {}", on_off(play_modes.shuffle));
+    println!("repeat: {}", on_off(play_modes.repeat));
+    println!("repeat-one: {}", on_off(play_modes.repeat_one));
+    println!("crossfade: {}", on_off(play_modes.crossfade));
+  }
+  Ok(())
+}
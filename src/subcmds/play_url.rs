@@ -0,0 +1,51 @@
+use crate::{ArgMatchesExt, Result};
+use clap::{Command, Arg, ArgAction, ArgMatches};
+use ronor::Sonos;
+use url::Url;
+
+pub const NAME: &str = "play-url";
+
+pub fn build() -> Command {
+  Command::new(NAME)
+    .about("Play a stream, such as internet radio, on a group")
+    .arg(crate::household_arg())
+    .arg(
+      Arg::new("STATION")
+        .short('n')
+        .long("name")
+        .num_args(1)
+        .value_name("STATION")
+        .help("Name to show for the stream")
+    )
+    .arg(
+      Arg::new("NO_PLAY")
+        .long("no-play")
+        .action(ArgAction::SetTrue)
+        .help("Do not start playback")
+    )
+    .arg(
+      Arg::new("GROUP")
+        .required(true)
+        .help("Name of the group")
+    )
+    .arg(
+      Arg::new("URL")
+        .required(true)
+        .value_parser(value_parser!(Url))
+        .help("Location of the stream")
+    )
+}
+
+pub fn run(sonos: &mut Sonos, matches: &ArgMatches) -> Result<()> {
+  let household = matches.household(sonos)?;
+  let targets = sonos.get_groups(&household)?;
+  let group = matches.group(&targets.groups)?;
+  let session = sonos.join_or_create_session(group, "guru.blind", "ronor", None)?;
+  sonos.load_stream_url(
+    &session,
+    matches.get_one::<Url>("URL").unwrap(),
+    matches.get_one::<String>("STATION").map(String::as_str),
+    !matches.get_flag("NO_PLAY")
+  )?;
+  Ok(())
+}
@@ -0,0 +1,45 @@
+use crate::scene::Scene;
+use crate::{ArgMatchesExt, Result};
+use clap::{Command, Arg, ArgMatches};
+use ronor::Sonos;
+
+pub const NAME: &str = "scene";
+
+pub fn build() -> Command {
+  Command::new(NAME)
+    .about("Save and restore grouping, volumes and loaded content of a household")
+    .subcommand_required(true)
+    .subcommand(Command::new("list").about("List saved scenes"))
+    .subcommand(
+      Command::new("save")
+        .about("Save the current state as a scene")
+        .arg(crate::household_arg())
+        .arg(Arg::new("NAME").required(true))
+    )
+    .subcommand(
+      Command::new("restore")
+        .about("Restore a saved scene")
+        .arg(crate::household_arg())
+        .arg(Arg::new("NAME").required(true))
+    )
+}
+
+pub fn run(sonos: &mut Sonos, matches: &ArgMatches) -> Result<()> {
+  match matches.subcommand() {
+    Some(("list", _)) => {
+      for name in Scene::names()? {
+        println!("{}", name);
+      }
+    }
+    Some(("save", matches)) => {
+      let household = matches.household(sonos)?;
+      Scene::capture(sonos, &household)?.save(matches.get_one::<String>("NAME").unwrap())?;
+    }
+    Some(("restore", matches)) => {
+      let household = matches.household(sonos)?;
+      Scene::load(matches.get_one::<String>("NAME").unwrap())?.restore(sonos, &household)?;
+    }
+    _ => unreachable!()
+  }
+  Ok(())
+}
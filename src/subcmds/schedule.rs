@@ -0,0 +1,190 @@
+use crate::schedule::{Action, Entry, Schedule};
+use crate::{Result, ResultExt};
+use clap::{Command, Arg, ArgAction, ArgGroup, ArgMatches};
+use ronor::Sonos;
+
+pub const NAME: &str = "schedule";
+
+pub fn build() -> Command {
+  Command::new(NAME)
+    .about("Manage scheduled actions run by 'ronor daemon'")
+    .subcommand_required(true)
+    .subcommand(Command::new("list").about("List scheduled actions"))
+    .subcommand(
+      Command::new("add")
+        .about("Schedule a new action")
+        .subcommand_required(true)
+        .arg(Arg::new("NAME").required(true).help("A unique name for this entry"))
+        .arg(
+          Arg::new("AT")
+            .long("at")
+            .num_args(1)
+            .value_name("HH:MM")
+            .help("Time of day")
+        )
+        .arg(
+          Arg::new("DAYS")
+            .long("days")
+            .num_args(1)
+            .value_name("DAYS")
+            .requires("AT")
+            .help("Days of the week (example: mon-fri or sat,sun)")
+        )
+        .arg(
+          Arg::new("CRON")
+            .long("cron")
+            .num_args(1)
+            .value_name("EXPRESSION")
+            .help("Cron expression (example: '30 7 * * mon-fri')")
+        )
+        .group(ArgGroup::new("WHEN").args(["AT", "CRON"]).required(true))
+        .subcommand(
+          Command::new("load-favorite")
+            .about("Load a favorite")
+            .args(play_args())
+            .arg(Arg::new("FAVORITE").required(true))
+            .arg(Arg::new("GROUP").required(true))
+        )
+        .subcommand(
+          Command::new("load-playlist")
+            .about("Load a playlist")
+            .args(play_args())
+            .arg(Arg::new("PLAYLIST").required(true))
+            .arg(Arg::new("GROUP").required(true))
+        )
+        .subcommand(
+          Command::new("set-volume")
+            .about("Set group volume")
+            .arg(
+              Arg::new("VOLUME")
+                .required(true)
+                .value_parser(value_parser!(u8).range(0..=100))
+            )
+            .arg(Arg::new("GROUP").required(true))
+        )
+        .subcommand(
+          Command::new("speak")
+            .about("Speak a text or a scraped web resource")
+            .arg(Arg::new("TEXT").long("text").num_args(1))
+            .arg(Arg::new("SCRAPE").long("scrape").num_args(1).value_name("URI"))
+            .group(ArgGroup::new("SOURCE").args(["TEXT", "SCRAPE"]).required(true))
+            .arg(
+              Arg::new("LANGUAGE")
+                .short('l')
+                .long("language")
+                .num_args(1)
+                .conflicts_with("SCRAPE")
+            )
+            .arg(Arg::new("PLAYER").required(true))
+        )
+        .subcommand(
+          Command::new("pause")
+            .about("Pause playback")
+            .arg(Arg::new("GROUP").required(true))
+        )
+    )
+    .subcommand(
+      Command::new("remove")
+        .about("Remove a scheduled action")
+        .arg(Arg::new("NAME").required(true))
+    )
+}
+
+fn play_args() -> Vec<Arg> {
+  vec![
+    Arg::new("NO_PLAY")
+      .long("no-play")
+      .action(ArgAction::SetTrue)
+      .help("Do not start playback"),
+    Arg::new("REPEAT").short('r').long("repeat").action(ArgAction::SetTrue),
+    Arg::new("REPEAT_ONE")
+      .short('o')
+      .long("repeat-one")
+      .action(ArgAction::SetTrue),
+    Arg::new("CROSSFADE")
+      .short('c')
+      .long("crossfade")
+      .action(ArgAction::SetTrue),
+    Arg::new("SHUFFLE")
+      .short('s')
+      .long("shuffle")
+      .action(ArgAction::SetTrue),
+  ]
+}
+
+pub fn run(_sonos: &mut Sonos, matches: &ArgMatches) -> Result<()> {
+  let path = Schedule::path()?;
+  let mut schedule = Schedule::load(&path)?;
+  match matches.subcommand() {
+    Some(("list", _)) => {
+      for entry in schedule.entries.iter() {
+        println!("{}", entry);
+      }
+    }
+    Some(("add", matches)) => {
+      let name = matches.get_one::<String>("NAME").unwrap();
+      if schedule.entries.iter().any(|entry| &entry.name == name) {
+        return Err(format!("An entry named '{}' already exists", name).into());
+      }
+      let entry = Entry {
+        name: name.to_string(),
+        cron: matches.get_one::<String>("CRON").cloned(),
+        time: matches.get_one::<String>("AT").cloned(),
+        days: matches.get_one::<String>("DAYS").cloned(),
+        action: action(matches)?
+      };
+      entry.cron().chain_err(|| "Invalid schedule")?;
+      schedule.entries.push(entry);
+      schedule.save(&path)?;
+    }
+    Some(("remove", matches)) => {
+      let name = matches.get_one::<String>("NAME").unwrap();
+      let count = schedule.entries.len();
+      schedule.entries.retain(|entry| &entry.name != name);
+      if schedule.entries.len() == count {
+        return Err(format!("No entry named '{}'", name).into());
+      }
+      schedule.save(&path)?;
+    }
+    _ => unreachable!()
+  }
+  Ok(())
+}
+
+fn action(matches: &ArgMatches) -> Result<Action> {
+  let string = |matches: &ArgMatches, id: &str| matches.get_one::<String>(id).cloned();
+  Ok(match matches.subcommand() {
+    Some(("load-favorite", matches)) => Action::LoadFavorite {
+      group: string(matches, "GROUP").unwrap(),
+      favorite: string(matches, "FAVORITE").unwrap(),
+      play: !matches.get_flag("NO_PLAY"),
+      repeat: matches.get_flag("REPEAT"),
+      repeat_one: matches.get_flag("REPEAT_ONE"),
+      crossfade: matches.get_flag("CROSSFADE"),
+      shuffle: matches.get_flag("SHUFFLE")
+    },
+    Some(("load-playlist", matches)) => Action::LoadPlaylist {
+      group: string(matches, "GROUP").unwrap(),
+      playlist: string(matches, "PLAYLIST").unwrap(),
+      play: !matches.get_flag("NO_PLAY"),
+      repeat: matches.get_flag("REPEAT"),
+      repeat_one: matches.get_flag("REPEAT_ONE"),
+      crossfade: matches.get_flag("CROSSFADE"),
+      shuffle: matches.get_flag("SHUFFLE")
+    },
+    Some(("set-volume", matches)) => Action::SetVolume {
+      group: string(matches, "GROUP").unwrap(),
+      volume: *matches.get_one::<u8>("VOLUME").unwrap()
+    },
+    Some(("speak", matches)) => Action::Speak {
+      player: string(matches, "PLAYER").unwrap(),
+      text: string(matches, "TEXT"),
+      scrape: string(matches, "SCRAPE"),
+      language: string(matches, "LANGUAGE")
+    },
+    Some(("pause", matches)) => Action::Pause {
+      group: string(matches, "GROUP").unwrap()
+    },
+    _ => unreachable!()
+  })
+}
@@ -0,0 +1,62 @@
+use crate::{ArgMatchesExt, Result, ResultExt};
+use clap::{Command, Arg, ArgMatches};
+use ronor::Sonos;
+use std::fs::read_to_string;
+
+pub const NAME: &str = "serve";
+
+/// Environment variable holding the bearer token.
+const TOKEN: &str = "RONOR_SERVE_TOKEN";
+
+pub fn build() -> Command {
+  Command::new(NAME)
+    .about("Offer control of the household as a web remote and JSON API")
+    .after_help(
+      "Open http://HOST:PORT/ in a browser for the remote control page.  \
+       API routes include GET /groups, POST /groups/NAME/play, \
+       PUT /players/NAME/volume and POST /players/NAME/speak.  \
+       Clients do not need the Sonos credentials, so protect the API \
+       with a bearer token when listening on anything but the loopback \
+       interface.  The token is read from --token-file, or from the \
+       RONOR_SERVE_TOKEN environment variable."
+    )
+    .arg(crate::household_arg())
+    .arg(
+      Arg::new("LISTEN")
+        .short('l')
+        .long("listen")
+        .num_args(1)
+        .value_name("HOST:PORT")
+        .default_value("127.0.0.1:8080")
+        .help("Where to accept connections")
+    )
+    .arg(
+      Arg::new("TOKEN_FILE")
+        .long("token-file")
+        .num_args(1)
+        .value_name("FILE")
+        .help("Require the bearer token in FILE from clients")
+    )
+}
+
+pub fn run(sonos: &mut Sonos, matches: &ArgMatches) -> Result<()> {
+  let household = matches.household(sonos)?;
+  let token = match matches.get_one::<String>("TOKEN_FILE") {
+    Some(path) => Some(
+      read_to_string(path)
+        .chain_err(|| format!("Failed to read token from '{}'", path))?
+        .trim_end_matches(['\r', '\n'])
+        .to_string()
+    ),
+    None => std::env::var(TOKEN).ok()
+  };
+  if token.as_deref() == Some("") {
+    return Err("The bearer token is empty".into());
+  }
+  crate::gateway::serve(
+    sonos,
+    &household,
+    matches.get_one::<String>("LISTEN").unwrap(),
+    token.as_deref()
+  )
+}
@@ -25,7 +25,7 @@ pub fn build() -> Command {
     )
     .group(
       ArgGroup::new("TARGET")
-        .args(&["GROUP", "PLAYER"])
+        .args(["GROUP", "PLAYER"])
         .required(true)
     )
 }
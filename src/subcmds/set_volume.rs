@@ -24,7 +24,7 @@ pub fn build() -> Command {
     )
     .group(
       ArgGroup::new("RELATIVE")
-        .args(&["INCREMENT", "DECREMENT"])
+        .args(["INCREMENT", "DECREMENT"])
     )
     .arg(
       Arg::new("GROUP")
@@ -42,7 +42,7 @@ pub fn build() -> Command {
     )
     .group(
       ArgGroup::new("TARGET")
-        .args(&["GROUP", "PLAYER"])
+        .args(["GROUP", "PLAYER"])
         .required(true)
     )
     .arg(
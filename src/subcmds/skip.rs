@@ -1,6 +1,6 @@
-use crate::{ArgMatchesExt, Result};
+use crate::{ArgMatchesExt, Result, ResultExt};
 use clap::{Command, Arg, ArgGroup, ArgMatches};
-use ronor::Sonos;
+use ronor::{PlaybackState, Sonos};
 
 pub const NAME: &str = "skip";
 
@@ -20,7 +20,14 @@ pub fn build() -> Command {
         .long("previous-track")
         .help("Skip to previous track")
     )
-    .group(ArgGroup::new("DIRECTION").args(&["NEXT", "PREVIOUS"]))
+    .arg(
+      Arg::new("TO")
+        .long("to")
+        .num_args(1)
+        .value_name("ITEM_ID")
+        .help("Skip to an item of a queue loaded by ronor (see 'ronor up-next')")
+    )
+    .group(ArgGroup::new("DIRECTION").args(["NEXT", "PREVIOUS", "TO"]))
     .arg(Arg::new("GROUP").required(true))
 }
 
@@ -28,6 +35,28 @@ pub fn run(sonos: &mut Sonos, matches: &ArgMatches) -> Result<()> {
   let household = matches.household(sonos)?;
   let targets = sonos.get_groups(&household)?;
   let group = matches.group(&targets.groups)?;
+  if let Some(item_id) = matches.get_one::<String>("TO") {
+    let status = sonos.get_playback_status(group)?;
+    let metadata = sonos.get_metadata_status(group)?;
+    if let Some(policies) = metadata.current_item.and_then(|item| item.policies) {
+      if !policies.can_skip_to_item {
+        return Err("The current content does not allow skipping to an item".into());
+      }
+      if policies.limited_skips && policies.skips_remaining < 1.0 {
+        return Err("No skips remaining".into());
+      }
+    }
+    let session = sonos
+      .join_session(group, "guru.blind", "ronor")
+      .chain_err(|| format!("{} is not playing a queue loaded by ronor", group.name))?;
+    sonos.skip_to_item(
+      &session,
+      item_id,
+      status.queue_version.as_deref(),
+      status.playback_state == PlaybackState::Playing
+    )?;
+    return Ok(());
+  }
   if matches.contains_id("NEXT") {
     sonos.skip_to_next_track(group)
   } else {
@@ -0,0 +1,230 @@
+use crate::fade::{fade_while, Curve, Outcome, Target};
+use crate::{ArgMatchesExt, ErrorKind, Result, ResultExt};
+use clap::{builder::PossibleValuesParser, Command, Arg, ArgAction, ArgMatches};
+use humantime::{format_duration, parse_duration};
+use ronor::{Group, HouseholdSnapshot, Sonos};
+use std::fs::{read_to_string, remove_file, write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use xdg::BaseDirectories;
+
+pub const NAME: &str = "sleep";
+
+/// Passes the id of a timer on to the detached process which runs it.
+const TIMER_ID: &str = "RONOR_SLEEP_TIMER";
+
+pub fn build() -> Command {
+  Command::new(NAME)
+    .about("Pause a group after some time")
+    .after_help(
+      "The timer runs in a detached process unless --foreground is given.  \
+       Only one timer per group is active, starting a new one replaces the old."
+    )
+    .arg(crate::household_arg())
+    .arg(
+      Arg::new("FADE")
+        .short('f')
+        .long("fade")
+        .num_args(1)
+        .value_name("DURATION")
+        .help("Fade out during the last part of the timer and restore the volume after pausing")
+    )
+    .arg(
+      Arg::new("CURVE")
+        .short('c')
+        .long("curve")
+        .num_args(1)
+        .value_parser(PossibleValuesParser::new(Curve::NAMES))
+        .default_value("linear")
+        .requires("FADE")
+    )
+    .arg(
+      Arg::new("FOREGROUND")
+        .long("foreground")
+        .action(ArgAction::SetTrue)
+        .help("Do not detach from the terminal")
+    )
+    .arg(
+      Arg::new("CANCEL")
+        .long("cancel")
+        .num_args(1)
+        .value_name("GROUP")
+        .conflicts_with_all(["DURATION", "FADE", "FOREGROUND"])
+        .help("Cancel the sleep timer of a group")
+    )
+    .arg(
+      Arg::new("DURATION")
+        .required_unless_present("CANCEL")
+        .help("Time until playback is paused (example: 30m)")
+    )
+    .arg(
+      Arg::new("GROUP")
+        .required_unless_present("CANCEL")
+        .help("Name of the group")
+    )
+}
+
+pub fn run(sonos: &mut Sonos, matches: &ArgMatches) -> Result<()> {
+  let household = matches.household(sonos)?;
+  let targets = HouseholdSnapshot::from(sonos.get_groups(&household)?);
+  if let Some(group_name) = matches.get_one::<String>("CANCEL") {
+    let group = targets
+      .group_by_name(group_name)
+      .ok_or_else(|| ErrorKind::UnknownGroup(group_name.to_string()))?;
+    let path = timer_path(group)?;
+    if !path.exists() {
+      return Err(format!("No sleep timer for {}", group.name).into());
+    }
+    remove_file(path)?;
+    return Ok(());
+  }
+  let group = matches.group(targets.groups())?;
+  let duration = parse_duration(matches.get_one::<String>("DURATION").unwrap())
+    .chain_err(|| "Failed to parse duration")?;
+  let fade = match matches.get_one::<String>("FADE") {
+    Some(fade) => Some(parse_duration(fade).chain_err(|| "Failed to parse fade duration")?),
+    None => None
+  };
+  let curve: Curve = matches.get_one::<String>("CURVE").unwrap().parse()?;
+  let path = timer_path(group)?;
+  if !matches.get_flag("FOREGROUND") {
+    return detach(group, duration, &path);
+  }
+  let id = match std::env::var(TIMER_ID) {
+    Ok(id) => id,
+    Err(_) => {
+      let id = process::id().to_string();
+      write(&path, &id)?;
+      id
+    }
+  };
+  let active = || is_active(&path, &id);
+  let result = sleep(sonos, group, duration, fade, curve, active);
+  if active() {
+    remove_file(&path)?;
+  }
+  result
+}
+
+/// Wait for `duration`, then pause `group`.
+///
+/// `active` is polled regularly, the timer is abandoned once it returns false.
+pub fn sleep<F: Fn() -> bool>(
+  sonos: &mut Sonos,
+  group: &Group,
+  duration: Duration,
+  fade: Option<Duration>,
+  curve: Curve,
+  active: F
+) -> Result<()> {
+  let fade = fade.map(|fade| fade.min(duration));
+  let deadline = Instant::now() + duration - fade.unwrap_or_default();
+  while let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+    if !active() {
+      return Ok(());
+    }
+    thread::sleep(remaining.min(Duration::from_secs(1)));
+  }
+  if !active() {
+    return Ok(());
+  }
+  let target = Target::Group(group);
+  let volume = target.volume(sonos)?;
+  let outcome = match fade {
+    Some(fade) => Some(fade_while(sonos, target, 0, fade, curve, &active)?),
+    None => None
+  };
+  let (pause, restore) = finish(outcome, volume);
+  if pause {
+    sonos.pause_or_stop(group)?;
+  }
+  if let Some(volume) = restore {
+    target.set_volume(sonos, volume)?;
+  }
+  Ok(())
+}
+
+/// Whether to pause once the fade is over, and which volume to restore
+/// afterwards.  `volume` is what the group was set to before fading.
+fn finish(outcome: Option<Outcome>, volume: u8) -> (bool, Option<u8>) {
+  match outcome {
+    None => (true, None),
+    Some(Outcome::Completed) => (true, Some(volume)),
+    // Someone else chose a volume, keep it but still pause.
+    Some(Outcome::Interrupted(_)) => (true, None),
+    Some(Outcome::Cancelled) => (false, Some(volume))
+  }
+}
+
+/// Start the timer in a separate process.
+///
+/// The timer file is written before, so that the timer can be cancelled
+/// right away.
+fn detach(group: &Group, duration: Duration, path: &Path) -> Result<()> {
+  let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos();
+  let id = format!("{}-{}", process::id(), nanos);
+  write(path, &id)?;
+  let mut command = process::Command::new(std::env::current_exe()?);
+  command
+    .args(std::env::args_os().skip(1))
+    .arg("--foreground")
+    .env(TIMER_ID, &id)
+    .stdin(process::Stdio::null())
+    .stdout(process::Stdio::null())
+    .stderr(process::Stdio::null());
+  #[cfg(unix)]
+  {
+    use std::os::unix::process::CommandExt;
+    command.process_group(0);
+  }
+  if let Err(e) = command.spawn() {
+    let _ = remove_file(path);
+    return Err(e).chain_err(|| "Failed to start sleep timer");
+  }
+  println!(
+    "{} will pause in {}, cancel with 'ronor sleep --cancel \"{}\"'",
+    group.name,
+    format_duration(duration),
+    group.name
+  );
+  Ok(())
+}
+
+/// Group ids change with membership, so timers are keyed by the coordinator.
+fn timer_path(group: &Group) -> Result<PathBuf> {
+  Ok(
+    BaseDirectories::with_prefix("ronor")?
+      .place_state_file(format!("sleep/{}", group.coordinator_id))?
+  )
+}
+
+fn is_active(path: &Path, id: &str) -> bool {
+  read_to_string(path).is_ok_and(|content| content == id)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn pauses_without_fade() {
+    assert_eq!(finish(None, 30), (true, None));
+  }
+
+  #[test]
+  fn restores_volume_after_fade() {
+    assert_eq!(finish(Some(Outcome::Completed), 30), (true, Some(30)));
+  }
+
+  #[test]
+  fn pauses_when_fade_is_interrupted() {
+    assert_eq!(finish(Some(Outcome::Interrupted(12)), 30), (true, None));
+  }
+
+  #[test]
+  fn cancelling_only_restores_volume() {
+    assert_eq!(finish(Some(Outcome::Cancelled), 30), (false, Some(30)));
+  }
+}
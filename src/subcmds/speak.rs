@@ -1,10 +1,18 @@
+use crate::cache::{self, ClipCache, Key};
+use crate::encoder::Encoder;
+use crate::file_server::FileServer;
+use crate::interrupt::PausedPlayback;
+use crate::ssml::{self, Segment};
 use crate::{ArgMatchesExt, Result, ResultExt};
-use clap::{Command, Arg, ArgGroup, ArgMatches};
-use ronor::Sonos;
+use clap::{builder::PossibleValuesParser, Command, Arg, ArgAction, ArgGroup, ArgMatches};
+use ronor::{HouseholdSnapshot, Player, Sonos};
 use scraper::{Html, Selector};
 use std::collections::HashMap;
-use std::io::Write;
+use std::fs::read_to_string;
+use std::io::{Read, Write};
 use std::process;
+use std::thread;
+use std::time::{Duration, Instant};
 use url::Url;
 
 pub const NAME: &str = "speak";
@@ -12,6 +20,11 @@ pub const NAME: &str = "speak";
 pub fn build() -> Command {
   Command::new(NAME)
     .about("Send synthetic speech to a player")
+    .after_help(
+      "Text is read from STDIN unless --file or --scrape is given.  \
+       SSML documents (<speak>...</speak>) are detected automatically.  \
+       Long texts are split into several consecutive audio clips."
+    )
     .arg(crate::household_arg())
     .arg(
       Arg::new("SCRAPE")
@@ -20,27 +33,96 @@ pub fn build() -> Command {
         .value_name("URI")
         .help("Scrape a specific web resource instead of taking text from STDIN")
     )
+    .arg(
+      Arg::new("FILE")
+        .short('f')
+        .long("file")
+        .num_args(1)
+        .value_name("PATH")
+        .help("Read text or SSML from a file instead of STDIN")
+    )
+    .group(ArgGroup::new("SOURCE").args(["SCRAPE", "FILE"]))
     .arg(
       Arg::new("LANGUAGE")
         .short('l')
         .long("language")
         .num_args(1)
-        .help("What language is the text coming from STDIN")
+        .conflicts_with("SCRAPE")
+        .help("What language is the text in (SSML may switch with xml:lang)")
+    )
+    .arg(
+      Arg::new("SSML")
+        .long("ssml")
+        .action(ArgAction::SetTrue)
+        .help("Treat the text as SSML even if it does not look like it")
+    )
+    .arg(
+      Arg::new("MAX_CHARS")
+        .long("max-chars")
+        .num_args(1)
+        .value_name("COUNT")
+        .value_parser(value_parser!(usize))
+        .default_value(MAX_CHARS.to_string())
+        .help("Split text into clips of at most this many characters")
+    )
+    .arg(
+      Arg::new("ENCODER")
+        .short('e')
+        .long("encoder")
+        .num_args(1)
+        .value_parser(PossibleValuesParser::new(Encoder::NAMES))
+        .default_value("mp3")
+        .help("How to encode the synthesized speech")
+    )
+    .arg(
+      Arg::new("NO_CACHE")
+        .long("no-cache")
+        .action(ArgAction::SetTrue)
+        .help("Do not reuse or store clips in the clip cache")
+    )
+    .arg(
+      Arg::new("CACHE_SIZE")
+        .long("cache-size")
+        .num_args(1)
+        .value_name("SIZE")
+        .default_value("100M")
+        .help("Upper bound for the size of the clip cache")
+    )
+    .arg(
+      Arg::new("SERVE")
+        .long("serve")
+        .num_args(1)
+        .value_name("HOST:PORT")
+        .conflicts_with("NO_CACHE")
+        .help("Serve clips from the cache on this address instead of uploading them")
     )
-    .group(ArgGroup::new("SOURCE").args(&["SCRAPE", "LANGUAGE"]))
     .arg(
       Arg::new("WORDS_PER_MINUTE")
         .short('s')
         .long("speed")
         .num_args(1)
-        .default_value("250")
+        .default_value(WORDS_PER_MINUTE)
     )
     .arg(
       Arg::new("VOLUME")
         .short('v')
         .long("volume")
         .num_args(1)
-        .default_value("75")
+        .value_parser(value_parser!(u8).range(0..=100))
+        .default_value((AMPLITUDE / 2).to_string())
+    )
+    .arg(
+      Arg::new("CLIP_VOLUME")
+        .long("clip-volume")
+        .num_args(1)
+        .value_parser(value_parser!(u8).range(0..=100))
+        .help("Player volume for the announcement in percent")
+    )
+    .arg(
+      Arg::new("INTERRUPT")
+        .long("interrupt")
+        .action(ArgAction::SetTrue)
+        .help("Pause the group instead of ducking and resume it afterwards")
     )
     .arg(
       Arg::new("PLAYER")
@@ -51,79 +133,253 @@ pub fn build() -> Command {
 
 pub fn run(sonos: &mut Sonos, matches: &ArgMatches) -> Result<()> {
   let household = matches.household(sonos)?;
-  let targets = sonos.get_groups(&household)?;
-  let player = matches.player(&targets.players)?;
-  let mut args = vec![
-    String::from("-w"),
-    String::from("/dev/stdout"),
-    String::from("--stdin"),
-  ];
-  let text = match matches.get_one::<String>("SCRAPE") {
-    Some(uri) => match scrapers().get(uri) {
-      Some(scraper) => {
-        let (language, text) = scraper(uri)?;
-        args.extend(vec![String::from("-v"), language]);
-        Some(text)
-      }
-      None => return Err("Scrape URI not supported".into())
-    },
-    None => None
+  let targets = HouseholdSnapshot::from(sonos.get_groups(&household)?);
+  let player = matches.player(targets.players())?;
+  let (language, text) = match matches.get_one::<String>("SCRAPE") {
+    Some(uri) => {
+      let (language, text) = scrape(uri)?;
+      print!("{}", &text);
+      (Some(language), text)
+    }
+    None => {
+      let text = match matches.get_one::<String>("FILE") {
+        Some(path) => {
+          read_to_string(path).chain_err(|| format!("Failed to read '{}'", path))?
+        }
+        None => {
+          let mut text = String::new();
+          std::io::stdin().read_to_string(&mut text)?;
+          text
+        }
+      };
+      (matches.get_one::<String>("LANGUAGE").cloned(), text)
+    }
   };
-  if let Some(language) = matches.get_one::<String>("LANGUAGE") {
-    args.extend(vec![String::from("-v"), language.to_string()]);
-  }
-  if let Some(wpm) = matches.get_one::<String>("WORDS_PER_MINUTE") {
-    args.extend(vec![String::from("-s"), wpm.to_string()]);
+  let segments = segments(text, language, matches.get_flag("SSML"))?;
+  let cache = if matches.get_flag("NO_CACHE") {
+    None
+  } else {
+    let max_size = cache::parse_size(matches.get_one::<String>("CACHE_SIZE").unwrap())?;
+    Some(ClipCache::open(max_size)?)
+  };
+  let server = match (matches.get_one::<String>("SERVE"), &cache) {
+    (Some(address), Some(cache)) => Some(FileServer::start(address, cache.directory())?),
+    _ => None
+  };
+  let options = Options {
+    words_per_minute: matches.get_one::<String>("WORDS_PER_MINUTE").unwrap().to_string(),
+    amplitude: *matches.get_one::<u8>("VOLUME").unwrap() * 2,
+    encoder: matches.get_one::<String>("ENCODER").unwrap().parse()?,
+    max_chars: *matches.get_one::<usize>("MAX_CHARS").unwrap(),
+    clip_volume: matches.get_one::<u8>("CLIP_VOLUME").copied(),
+    cache: cache.as_ref(),
+    server: server.as_ref()
+  };
+  if matches.get_flag("INTERRUPT") {
+    let group = targets
+      .group_of(&player.id)
+      .ok_or("Player is not part of any group")?;
+    let paused = PausedPlayback::pause(sonos, group)?;
+    let end = speak(sonos, player, segments, &options);
+    paused.resume_at(sonos, group, *end.as_ref().unwrap_or(&Instant::now()))?;
+    end?;
+  } else {
+    speak(sonos, player, segments, &options)?;
   }
-  if let Some(volume) = matches.get_one::<String>("VOLUME") {
-    let volume = volume.parse::<u8>()? * 2;
-    args.extend(vec![String::from("-a"), volume.to_string()]);
+  Ok(())
+}
+
+/// Default speed of speech.
+pub const WORDS_PER_MINUTE: &str = "250";
+/// Default `espeak` amplitude, twice the volume in percent.
+pub const AMPLITUDE: u8 = 150;
+/// Default maximum length of the text in a single clip.
+pub const MAX_CHARS: usize = 1000;
+
+/// How speech is synthesized and delivered to players.
+pub struct Options<'a> {
+  pub words_per_minute: String,
+  pub amplitude: u8,
+  pub encoder: Encoder,
+  pub max_chars: usize,
+  /// Volume for the audio clips, the player decides if missing.
+  pub clip_volume: Option<u8>,
+  pub cache: Option<&'a ClipCache>,
+  pub server: Option<&'a FileServer>
+}
+
+impl Default for Options<'_> {
+  fn default() -> Self {
+    Options {
+      words_per_minute: String::from(WORDS_PER_MINUTE),
+      amplitude: AMPLITUDE,
+      encoder: Encoder::Mp3,
+      max_chars: MAX_CHARS,
+      clip_volume: None,
+      cache: None,
+      server: None
+    }
   }
+}
 
-  let espeak = if text.is_some() {
-    process::Command::new("espeak")
-      .args(args)
-      .stdin(process::Stdio::piped())
-      .stdout(process::Stdio::piped())
-      .spawn()
+/// Turn plain text or SSML into segments ready for [`speak`].
+pub fn segments(text: String, language: Option<String>, ssml: bool) -> Result<Vec<Segment>> {
+  if ssml || ssml::is_ssml(&text) {
+    ssml::parse(&text, language.as_deref())
   } else {
-    process::Command::new("espeak")
-      .args(args)
-      .stdout(process::Stdio::piped())
-      .spawn()
+    Ok(vec![Segment::plain(language, text)])
+  }
+}
+
+/// Speak `segments` on `player`, one audio clip after the other.
+///
+/// Returns when the last clip is expected to finish playing.
+pub fn speak(
+  sonos: &mut Sonos,
+  player: &Player,
+  segments: Vec<Segment>,
+  options: &Options
+) -> Result<Instant> {
+  let chunks = ssml::chunk(segments, options.max_chars.max(1));
+  let mut previous: Option<(Instant, Duration)> = None;
+  for chunk in chunks.iter() {
+    let (url, duration) = prepare(
+      chunk,
+      &options.words_per_minute,
+      options.amplitude,
+      options.encoder,
+      options.cache,
+      options.server
+    )?;
+    if let Some((started, duration)) = previous {
+      if let Some(remaining) = duration.checked_sub(started.elapsed()) {
+        thread::sleep(remaining);
+      }
+    }
+    sonos.load_audio_clip(
+      player,
+      "guru.blind",
+      "ping",
+      None,
+      None,
+      options.clip_volume,
+      None,
+      Some(&url)
+    )?;
+    previous = Some((Instant::now(), duration));
+  }
+  if let (Some(_), Some((started, duration))) = (options.server, previous) {
+    // Keep serving until the player had a chance to fetch the last clip.
+    let linger = duration + Duration::from_secs(10);
+    if let Some(remaining) = linger.checked_sub(started.elapsed()) {
+      thread::sleep(remaining);
+    }
+  }
+  Ok(match previous {
+    Some((started, duration)) => started + duration,
+    None => Instant::now()
+  })
+}
+
+/// Synthesize a segment, or find it in the cache, and return a URL
+/// players can fetch it from along with its duration.
+fn prepare(
+  segment: &Segment,
+  wpm: &str,
+  amplitude: u8,
+  encoder: Encoder,
+  cache: Option<&ClipCache>,
+  server: Option<&FileServer>
+) -> Result<(Url, Duration)> {
+  let cache = match cache {
+    Some(cache) => cache,
+    None => {
+      let clip = encoder.encode(&espeak(segment, wpm, amplitude)?)?;
+      return Ok((upload(clip.data, clip.extension)?, clip.duration));
+    }
+  };
+  let markup = segment.to_espeak_markup();
+  let key = Key {
+    engine: "espeak",
+    language: segment.language.as_deref(),
+    markup: &markup,
+    words_per_minute: wpm,
+    amplitude,
+    encoder
+  };
+  let mut entry = match cache.get(&key)? {
+    Some(entry) => entry,
+    None => cache.insert(&key, encoder.encode(&espeak(segment, wpm, amplitude)?)?)?
+  };
+  let url = match (server, entry.url()) {
+    (Some(server), _) => server.url(&entry.file_name())?,
+    (None, Some(url)) => url,
+    (None, None) => {
+      let url = upload(cache.read(&entry)?, entry.extension())?;
+      cache.set_url(&mut entry, &url)?;
+      url
+    }
+  };
+  Ok((url, entry.duration()))
+}
+
+/// Synthesize a segment with espeak and return the resulting WAV data.
+pub fn espeak(segment: &Segment, wpm: &str, amplitude: u8) -> Result<Vec<u8>> {
+  run_espeak(segment, wpm, amplitude, true)
+}
+
+/// Speak a segment on the local sound device.
+pub fn say(segment: &Segment, wpm: &str, amplitude: u8) -> Result<()> {
+  run_espeak(segment, wpm, amplitude, false).map(|_| ())
+}
+
+fn run_espeak(segment: &Segment, wpm: &str, amplitude: u8, capture: bool) -> Result<Vec<u8>> {
+  let mut args = vec![String::from("-m")];
+  if capture {
+    args.extend(vec![String::from("-w"), String::from("/dev/stdout")]);
+  }
+  args.extend(vec![
+    String::from("--stdin"),
+    String::from("-s"),
+    wpm.to_string(),
+    String::from("-a"),
+    amplitude.to_string(),
+  ]);
+  if let Some(language) = &segment.language {
+    args.extend(vec![String::from("-v"), language.to_lowercase()]);
   }
-  .chain_err(|| "Failed to spawn 'espeak'")?;
-  if let Some(text) = text {
-    espeak.stdin.unwrap().write_all(text.as_bytes())?;
-    print!("{}", &text);
+  let mut espeak = process::Command::new("espeak")
+    .args(args)
+    .stdin(process::Stdio::piped())
+    .stdout(if capture {
+      process::Stdio::piped()
+    } else {
+      process::Stdio::null()
+    })
+    .spawn()
+    .chain_err(|| "Failed to spawn 'espeak'")?;
+  let mut stdin = espeak.stdin.take().unwrap();
+  let markup = segment.to_espeak_markup();
+  let writer = thread::spawn(move || stdin.write_all(markup.as_bytes()));
+  let output = espeak.wait_with_output()?;
+  writer.join().map_err(|_| "Failed to write to 'espeak'")??;
+  if !output.status.success() {
+    return Err("'espeak' failed".into());
   }
-  let mp3 = process::Command::new("ffmpeg")
-    .args(&["-i", "-", "-v", "fatal", "-b:a", "96k", "-f", "mp3", "-"])
-    .stdin(espeak.stdout.unwrap())
-    .output()
-    .chain_err(|| "Failed to spawn 'ffmpeg'")?
-    .stdout;
+  Ok(output.stdout)
+}
+
+fn upload(data: Vec<u8>, extension: &str) -> Result<Url> {
   let client = reqwest::blocking::Client::new();
   let url = client
-    .put("https://transfer.sh/espeak.mp3")
-    .body(mp3)
+    .put(format!("https://transfer.sh/espeak.{}", extension))
+    .body(data)
     .send()
     .chain_err(|| "Failed to send audio clip to transfer.sh")?
     .error_for_status()
     .chain_err(|| "Failed to upload audio clip to transfer.sh")?
     .text()?;
-  let url = Url::parse(&url).chain_err(|| "Failed to parse transfer.sh reply")?;
-  sonos.load_audio_clip(
-    player,
-    "guru.blind",
-    "ping",
-    None,
-    None,
-    None,
-    None,
-    Some(&url)
-  )?;
-  Ok(())
+  Url::parse(&url).chain_err(|| "Failed to parse transfer.sh reply")
 }
 
 type Scraper = fn(&str) -> Result<(String, String)>;
@@ -164,6 +420,16 @@ fn zamg_ac_at(uri: &str) -> Result<(String, String)> {
   Ok(("de".to_string(), s))
 }
 
+/// Scrape one of the predefined web resources.
+///
+/// Returns the language and the extracted text.
+pub fn scrape(uri: &str) -> Result<(String, String)> {
+  match scrapers().get(uri) {
+    Some(scraper) => scraper(uri),
+    None => Err("Scrape URI not supported".into())
+  }
+}
+
 fn scrapers() -> HashMap<String, Scraper> {
   let mut m: HashMap<_, Scraper> = HashMap::new();
   for region in &[
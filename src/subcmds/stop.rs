@@ -0,0 +1,33 @@
+use crate::{ArgMatchesExt, ErrorKind, Result};
+use clap::{Command, Arg, ArgMatches};
+use ronor::{PlaybackState, Sonos};
+
+pub const NAME: &str = "stop";
+
+pub fn build() -> Command {
+  Command::new(NAME)
+    .about("Stop playback for the given group")
+    .arg(crate::household_arg())
+    .arg(Arg::new("GROUP").help("Name of the group"))
+}
+
+pub fn run(sonos: &mut Sonos, matches: &ArgMatches) -> Result<()> {
+  let group_name = matches.get_one::<String>("GROUP");
+  let household = matches.household(sonos)?;
+  let mut found = false;
+  for group in sonos.get_groups(&household)?.groups.iter() {
+    if group_name.is_none_or(|name| name == &group.name) {
+      found = true;
+      if group.playback_state != PlaybackState::Idle {
+        sonos.stop(group)?;
+      }
+    }
+  }
+  if !found {
+    if let Some(group_name) = group_name {
+      return Err(ErrorKind::UnknownGroup(group_name.to_string()).into());
+    }
+    return Err("No groups found".into());
+  }
+  Ok(())
+}
@@ -1,6 +1,6 @@
 use crate::{ArgMatchesExt, ErrorKind, Result};
 use clap::{Command, Arg, ArgMatches};
-use ronor::Sonos;
+use ronor::{PlaybackState, Sonos};
 
 pub const NAME: &str = "toggle-play-pause";
 
@@ -16,9 +16,18 @@ pub fn run(sonos: &mut Sonos, matches: &ArgMatches) -> Result<()> {
   let household = matches.household(sonos)?;
   let mut found = false;
   for group in sonos.get_groups(&household)?.groups.iter() {
-    if group_name.map_or(true, |name| name == &group.name) {
+    if group_name.is_none_or(|name| name == &group.name) {
       found = true;
-      sonos.toggle_play_pause(group)?;
+      let status = sonos.get_playback_status(group)?;
+      let playing = matches!(
+        status.playback_state,
+        PlaybackState::Playing | PlaybackState::Buffering
+      );
+      if playing && !status.available_playback_actions.can_pause {
+        sonos.pause_or_stop(group)?;
+      } else {
+        sonos.toggle_play_pause(group)?;
+      }
     }
   }
   if !found {
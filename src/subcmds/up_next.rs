@@ -0,0 +1,49 @@
+use crate::{ArgMatchesExt, Result};
+use clap::{Command, Arg, ArgMatches};
+use ronor::{Item, Sonos};
+
+pub const NAME: &str = "up-next";
+
+pub fn build() -> Command {
+  Command::new(NAME)
+    .about("Show the current and the next item of a group")
+    .arg(crate::household_arg())
+    .arg(
+      Arg::new("GROUP")
+        .required(true)
+        .help("Name of the group")
+    )
+}
+
+pub fn run(sonos: &mut Sonos, matches: &ArgMatches) -> Result<()> {
+  let household = matches.household(sonos)?;
+  let targets = sonos.get_groups(&household)?;
+  let group = matches.group(&targets.groups)?;
+  let metadata = sonos.get_metadata_status(group)?;
+  if metadata.current_item.is_none() && metadata.next_item.is_none() {
+    return Err(format!("{} has no queue", group.name).into());
+  }
+  for (label, item) in [("current", &metadata.current_item), ("next", &metadata.next_item)] {
+    if let Some(item) = item {
+      println!("{}: {}", label, describe(item));
+    }
+  }
+  if let Some(policies) = metadata.current_item.and_then(|item| item.policies) {
+    if policies.limited_skips {
+      println!("skips remaining: {}", policies.skips_remaining);
+    }
+  }
+  Ok(())
+}
+
+fn describe(item: &Item) -> String {
+  let mut parts = Vec::new();
+  if let Some(id) = &item.id {
+    parts.push(format!("[{}]", id));
+  }
+  parts.push(item.track.name.clone().unwrap_or_else(|| String::from("Unknown")));
+  if let Some(artist) = &item.track.artist {
+    parts.push(format!("- {}", artist.name));
+  }
+  parts.join(" ")
+}
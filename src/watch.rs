@@ -0,0 +1,457 @@
+//! Detect changes in a household by polling.
+//!
+//! Not everyone can expose a callback URL to receive events from Sonos.
+//! A [`Watcher`] instead takes a [`Snapshot`] of a household at a regular
+//! interval and reports the differences between successive snapshots as
+//! [`Change`]s.
+
+use crate::{
+  Group, GroupId, GroupVolume, Household, HouseholdSnapshot, Item, MetadataStatus,
+  PlaybackState, PlaybackStatus, PlayerId, PlayerVolume, Result, Sonos
+};
+use std::collections::HashMap;
+use std::thread;
+use std::time::Duration;
+
+/// Something that happened in a household between two snapshots.
+///
+/// Groups are identified by their coordinator, since Sonos hands out a
+/// new group ID whenever the members of a group change.  Changes always
+/// carry the group ID from the newer snapshot.
+#[derive(Clone, Debug)]
+pub enum Change {
+  /// A player started coordinating a group.
+  GroupFormed(Group),
+  /// A player stopped coordinating a group.
+  GroupDissolved(Group),
+  /// A player was added to an existing group.
+  PlayerJoined { group: GroupId, player: PlayerId },
+  /// A player was removed from a group which still exists.
+  PlayerLeft { group: GroupId, player: PlayerId },
+  /// The current item or stream information of a group changed.
+  TrackChanged {
+    group: GroupId,
+    item: Option<Box<Item>>,
+    stream_info: Option<String>
+  },
+  /// The volume of a group, or of a single player if `player` is given,
+  /// changed.
+  VolumeChanged {
+    group: GroupId,
+    player: Option<PlayerId>,
+    volume: u8,
+    muted: bool
+  },
+  /// A group started playing, paused, buffered or went idle.
+  PlaybackStateChanged { group: GroupId, state: PlaybackState },
+  /// Favorites were added, removed or changed.
+  FavoritesVersionChanged { version: String }
+}
+
+/// Everything a [`Watcher`] knows about a household at one point in time.
+pub struct Snapshot {
+  pub household: HouseholdSnapshot,
+  pub playback: HashMap<GroupId, PlaybackStatus>,
+  pub metadata: HashMap<GroupId, MetadataStatus>,
+  pub group_volumes: HashMap<GroupId, GroupVolume>,
+  /// Empty unless player volumes are watched.
+  pub player_volumes: HashMap<PlayerId, PlayerVolume>,
+  pub favorites_version: String
+}
+
+impl Snapshot {
+  /// Fetch the current state of `household`, including the volume of
+  /// every player if `player_volumes` is set.
+  pub fn take(sonos: &mut Sonos, household: &Household, player_volumes: bool) -> Result<Self> {
+    let targets = HouseholdSnapshot::from(sonos.get_groups(household)?);
+    let mut playback = HashMap::new();
+    let mut metadata = HashMap::new();
+    let mut group_volumes = HashMap::new();
+    for group in targets.groups().iter() {
+      playback.insert(group.id.clone(), sonos.get_playback_status(group)?);
+      metadata.insert(group.id.clone(), sonos.get_metadata_status(group)?);
+      group_volumes.insert(group.id.clone(), sonos.get_group_volume(group)?);
+    }
+    let mut volumes = HashMap::new();
+    if player_volumes {
+      for player in targets.players().iter() {
+        volumes.insert(player.id.clone(), sonos.get_player_volume(player)?);
+      }
+    }
+    let favorites_version = sonos.get_favorites(household)?.version;
+    Ok(Snapshot {
+      household: targets,
+      playback,
+      metadata,
+      group_volumes,
+      player_volumes: volumes,
+      favorites_version
+    })
+  }
+
+  /// Everything that changed from `self` to `newer`.
+  pub fn diff(&self, newer: &Snapshot) -> Vec<Change> {
+    let mut changes = Vec::new();
+    for group in self.household.groups().iter() {
+      if newer.household.coordinated_by(&group.coordinator_id).is_none() {
+        changes.push(Change::GroupDissolved(group.clone()));
+      }
+    }
+    for group in newer.household.groups().iter() {
+      let old = match self.household.coordinated_by(&group.coordinator_id) {
+        Some(old) => old,
+        None => {
+          changes.push(Change::GroupFormed(group.clone()));
+          continue;
+        }
+      };
+      for player in group.player_ids.iter().filter(|id| !old.player_ids.contains(id)) {
+        changes.push(Change::PlayerJoined {
+          group: group.id.clone(),
+          player: player.clone()
+        });
+      }
+      for player in old.player_ids.iter().filter(|id| !group.player_ids.contains(id)) {
+        changes.push(Change::PlayerLeft {
+          group: group.id.clone(),
+          player: player.clone()
+        });
+      }
+      if let (Some(before), Some(after)) =
+        (self.metadata.get(&old.id), newer.metadata.get(&group.id))
+      {
+        if track_key(before) != track_key(after) {
+          changes.push(Change::TrackChanged {
+            group: group.id.clone(),
+            item: after.current_item.clone().map(Box::new),
+            stream_info: after.stream_info.clone()
+          });
+        }
+      }
+      if let (Some(before), Some(after)) =
+        (self.group_volumes.get(&old.id), newer.group_volumes.get(&group.id))
+      {
+        if (before.volume, before.muted) != (after.volume, after.muted) {
+          changes.push(Change::VolumeChanged {
+            group: group.id.clone(),
+            player: None,
+            volume: after.volume,
+            muted: after.muted
+          });
+        }
+      }
+      for player in group.player_ids.iter() {
+        if let (Some(before), Some(after)) =
+          (self.player_volumes.get(player), newer.player_volumes.get(player))
+        {
+          if (before.volume, before.muted) != (after.volume, after.muted) {
+            changes.push(Change::VolumeChanged {
+              group: group.id.clone(),
+              player: Some(player.clone()),
+              volume: after.volume,
+              muted: after.muted
+            });
+          }
+        }
+      }
+      if let (Some(before), Some(after)) =
+        (self.playback.get(&old.id), newer.playback.get(&group.id))
+      {
+        if before.playback_state != after.playback_state {
+          changes.push(Change::PlaybackStateChanged {
+            group: group.id.clone(),
+            state: after.playback_state
+          });
+        }
+      }
+    }
+    if self.favorites_version != newer.favorites_version {
+      changes.push(Change::FavoritesVersionChanged {
+        version: newer.favorites_version.clone()
+      });
+    }
+    changes
+  }
+}
+
+/// What identifies the track a group is playing.
+fn track_key(metadata: &MetadataStatus) -> (Option<&str>, Option<&str>, Option<&str>) {
+  let item = metadata.current_item.as_ref();
+  (
+    item.and_then(|item| item.id.as_deref()),
+    item.and_then(|item| item.track.name.as_deref()),
+    metadata.stream_info.as_deref()
+  )
+}
+
+/// Polls a household and reports what changed.
+pub struct Watcher {
+  household: Household,
+  /// How long to wait between two snapshots.
+  pub interval: Duration,
+  /// Also watch the volume of each player, at the cost of one request
+  /// per player and poll.
+  pub player_volumes: bool,
+  last: Option<Snapshot>
+}
+
+impl Watcher {
+  pub fn new(household: Household, interval: Duration) -> Self {
+    Watcher {
+      household,
+      interval,
+      player_volumes: false,
+      last: None
+    }
+  }
+
+  pub fn household(&self) -> &Household {
+    &self.household
+  }
+
+  /// The most recent snapshot, if any was taken yet.
+  pub fn snapshot(&self) -> Option<&Snapshot> {
+    self.last.as_ref()
+  }
+
+  /// Take a new snapshot and return what changed since the last one.
+  ///
+  /// The first poll only takes the initial snapshot and reports nothing.
+  pub fn poll(&mut self, sonos: &mut Sonos) -> Result<Vec<Change>> {
+    let snapshot = Snapshot::take(sonos, &self.household, self.player_volumes)?;
+    let changes = match &self.last {
+      Some(last) => last.diff(&snapshot),
+      None => Vec::new()
+    };
+    self.last = Some(snapshot);
+    Ok(changes)
+  }
+
+  /// Poll forever, passing every change to `f`.
+  ///
+  /// Returns as soon as polling or `f` fails.
+  pub fn watch<F>(&mut self, sonos: &mut Sonos, mut f: F) -> Result<()>
+  where
+    F: FnMut(&Snapshot, &Change) -> Result<()>
+  {
+    loop {
+      let changes = self.poll(sonos)?;
+      if let Some(snapshot) = &self.last {
+        for change in changes.iter() {
+          f(snapshot, change)?;
+        }
+      }
+      thread::sleep(self.interval);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::Groups;
+  use serde_json::{from_value, json, Value};
+
+  /// A household with groups given as `(id, members)`, the first member
+  /// being the coordinator.
+  fn household(groups: &[(&str, &[&str])]) -> HouseholdSnapshot {
+    let player = |id: &str| {
+      json!({
+        "isUnregistered": false,
+        "apiVersion": "1.1.0",
+        "deviceIds": [id],
+        "id": id,
+        "minApiVersion": "1.1.0",
+        "name": id,
+        "softwareVersion": "1.0",
+        "capabilities": ["PLAYBACK"],
+        "websocketUrl": "wss://localhost",
+        "virtualLineInSource": null
+      })
+    };
+    let players: Vec<Value> =
+      groups.iter().flat_map(|(_, members)| members.iter().map(|id| player(id))).collect();
+    let groups: Vec<Value> = groups
+      .iter()
+      .map(|(id, members)| {
+        json!({
+          "coordinatorId": members[0],
+          "id": id,
+          "playbackState": "PLAYBACK_STATE_PLAYING",
+          "playerIds": members,
+          "name": members[0]
+        })
+      })
+      .collect();
+    let groups: Groups =
+      from_value(json!({ "groups": groups, "players": players, "partial": false })).unwrap();
+    HouseholdSnapshot::from(groups)
+  }
+
+  fn playback(state: &str) -> PlaybackStatus {
+    from_value(json!({
+      "playbackState": state,
+      "queueVersion": null,
+      "itemId": null,
+      "positionMillis": 0,
+      "previousPositionMillis": 0,
+      "playModes": { "repeat": false, "repeatOne": false, "crossfade": false, "shuffle": false },
+      "availablePlaybackActions": {
+        "canSkip": true, "canSkipBack": true, "canSeek": true, "canRepeat": true,
+        "canRepeatOne": true, "canCrossfade": true, "canShuffle": true, "canPause": true,
+        "canStop": true
+      },
+      "isDucking": false
+    }))
+    .unwrap()
+  }
+
+  fn metadata(track: &str) -> MetadataStatus {
+    from_value(json!({
+      "container": null,
+      "currentItem": {
+        "id": null,
+        "track": {
+          "type": "track",
+          "canCrossfade": null,
+          "canSkip": null,
+          "advertisement": null,
+          "durationMillis": 180000,
+          "id": null,
+          "imageUrl": null,
+          "images": [],
+          "name": track,
+          "trackNumber": null,
+          "album": null,
+          "artist": null,
+          "author": null,
+          "narrator": null,
+          "replayGain": null,
+          "service": null,
+          "explicit": false,
+          "quality": { "bitDepth": 0.0, "sampleRate": 0.0, "lossless": false, "immersive": false }
+        },
+        "deleted": null,
+        "policies": null
+      },
+      "nextItem": null,
+      "streamInfo": null
+    }))
+    .unwrap()
+  }
+
+  fn volume(volume: u8) -> GroupVolume {
+    from_value(json!({ "volume": volume, "muted": false, "fixed": false })).unwrap()
+  }
+
+  fn player_volume(volume: u8) -> PlayerVolume {
+    from_value(json!({ "volume": volume, "muted": false, "fixed": false })).unwrap()
+  }
+
+  /// A snapshot where every group plays `track` at volume 20.
+  fn snapshot(groups: &[(&str, &[&str])], track: &str) -> Snapshot {
+    let household = household(groups);
+    let mut snapshot = Snapshot {
+      playback: HashMap::new(),
+      metadata: HashMap::new(),
+      group_volumes: HashMap::new(),
+      player_volumes: HashMap::new(),
+      favorites_version: String::from("1"),
+      household
+    };
+    for group in snapshot.household.groups().iter() {
+      snapshot.playback.insert(group.id.clone(), playback("PLAYBACK_STATE_PLAYING"));
+      snapshot.metadata.insert(group.id.clone(), metadata(track));
+      snapshot.group_volumes.insert(group.id.clone(), volume(20));
+    }
+    snapshot
+  }
+
+  fn group_id(id: &str) -> GroupId {
+    from_value(json!(id)).unwrap()
+  }
+
+  fn player_id(id: &str) -> PlayerId {
+    from_value(json!(id)).unwrap()
+  }
+
+  #[test]
+  fn nothing_changed() {
+    let groups: &[(&str, &[&str])] = &[("G1", &["A"]), ("G2", &["B", "C"])];
+    assert!(snapshot(groups, "One").diff(&snapshot(groups, "One")).is_empty());
+  }
+
+  #[test]
+  fn joining_keeps_the_group_of_the_coordinator() {
+    let before = snapshot(&[("G1", &["A"]), ("G2", &["B"])], "One");
+    let after = snapshot(&[("G3", &["A", "B"])], "One");
+    let changes = before.diff(&after);
+    assert_eq!(changes.len(), 2, "{:?}", changes);
+    assert!(matches!(&changes[0], Change::GroupDissolved(group) if group.id == group_id("G2")));
+    assert!(matches!(
+      &changes[1],
+      Change::PlayerJoined { group, player } if *group == group_id("G3") && *player == player_id("B")
+    ));
+  }
+
+  #[test]
+  fn leaving_forms_a_new_group() {
+    let before = snapshot(&[("G1", &["A", "B"])], "One");
+    let after = snapshot(&[("G2", &["A"]), ("G3", &["B"])], "One");
+    let changes = before.diff(&after);
+    assert_eq!(changes.len(), 2, "{:?}", changes);
+    assert!(matches!(
+      &changes[0],
+      Change::PlayerLeft { group, player } if *group == group_id("G2") && *player == player_id("B")
+    ));
+    assert!(matches!(
+      &changes[1],
+      Change::GroupFormed(group) if group.id == group_id("G3")
+    ));
+  }
+
+  #[test]
+  fn track_changes_follow_new_group_ids() {
+    let before = snapshot(&[("G1", &["A"])], "One");
+    let after = snapshot(&[("G2", &["A"])], "Two");
+    let changes = before.diff(&after);
+    assert_eq!(changes.len(), 1, "{:?}", changes);
+    match &changes[0] {
+      Change::TrackChanged { group, item, .. } => {
+        assert_eq!(*group, group_id("G2"));
+        assert_eq!(item.as_ref().unwrap().track.name.as_deref(), Some("Two"));
+      }
+      change => panic!("unexpected {:?}", change)
+    }
+  }
+
+  #[test]
+  fn volume_playback_and_favorites() {
+    let groups: &[(&str, &[&str])] = &[("G1", &["A", "B"])];
+    let mut before = snapshot(groups, "One");
+    before.player_volumes.insert(player_id("B"), player_volume(10));
+    let mut after = snapshot(groups, "One");
+    after.player_volumes.insert(player_id("B"), player_volume(15));
+    after.group_volumes.insert(group_id("G1"), volume(30));
+    after.playback.insert(group_id("G1"), playback("PLAYBACK_STATE_PAUSED"));
+    after.favorites_version = String::from("2");
+    let changes = before.diff(&after);
+    assert_eq!(changes.len(), 4, "{:?}", changes);
+    assert!(matches!(
+      &changes[0],
+      Change::VolumeChanged { player: None, volume: 30, muted: false, .. }
+    ));
+    assert!(matches!(
+      &changes[1],
+      Change::VolumeChanged { player: Some(player), volume: 15, .. } if *player == player_id("B")
+    ));
+    assert!(matches!(
+      &changes[2],
+      Change::PlaybackStateChanged { state: PlaybackState::Paused, .. }
+    ));
+    assert!(matches!(
+      &changes[3],
+      Change::FavoritesVersionChanged { version } if version == "2"
+    ));
+  }
+}